@@ -2,30 +2,108 @@
 //!
 //! Use `lsapp` to scan .desktop files and customize their display. Useful for creating a
 //! simple program launcher by combining with fzf/skim
+//!
+//! `--key` starts every line with the path of the entry's .desktop file, a field that fzf's
+//! `--with-nth` can hide while `lsapp preview` and `lsapp run` still receive it:
+//!
+//! ```sh
+//! lsapp --key -d name,comment \
+//!     | fzf --delimiter '\t' --with-nth 2.. --preview 'lsapp preview {1}' \
+//!     | cut -f1 | xargs -r lsapp run
+//! ```
+//!
+//! `lsapp pick` runs that pipeline itself, with `--finder sk` or `LSAPP_FINDER` to use skim.
+//! `--print0` ends each entry with a NUL instead of a line break, for `fzf --read0` and
+//! `xargs -0`.
+//!
+//! `--mode rofi|wofi|dmenu|fuzzel` prints the listing as input for a launcher menu, with icons,
+//! and launches the entry named by a chosen line given back as an argument:
+//!
+//! ```sh
+//! rofi -show apps -modi 'apps:lsapp --mode rofi'
+//! lsapp --mode fuzzel | fuzzel --dmenu | xargs -r -d '\n' lsapp --mode fuzzel --
+//! ```
+//!
+//! Default sources, columns, separator, quoting, language, filter and sort can be kept in
+//! `$XDG_CONFIG_HOME/lsapp/config.toml`, with named profiles chosen by `--profile`; see the
+//! `config` module. `lsapp config show` prints the settings in effect.
+//!
+//! `lsapp completions <bash|zsh|fish>` prints a completion script, which completes desktop file
+//! IDs and column names from the scanned entries as well as flags.
 
-mod parser;
-
+use std::cmp::Ordering;
+use std::env;
+use std::fs;
+use std::io::{stdout, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
 use std::str::FromStr;
+use std::time::Duration;
 
-use clap::clap_app;
+use clap::{clap_app, App, ArgMatches};
 use color_eyre::{Report, Result};
 use eyre::WrapErr;
 use thiserror::Error;
 
-const DEFAULT_SOURCES: &[&'static str] = &[
-    "/usr/share/applications",
-    "/usr/local/share/applications",
-    "~/.local/share/applications",
+use lsapp::{Cache, DesktopEntry, Template, Watcher};
+use lsapp::collate::collate;
+use lsapp::complete::{Candidate, Context, Position, Shell, SHELLS};
+use lsapp::config::{self, Config};
+use lsapp::entry::DESKTOP_ENTRY;
+use lsapp::exec::{Exec, ExecError, Target};
+use lsapp::filter::Filter;
+use lsapp::icon::IconFinder;
+use lsapp::menu::{Item, Mode, MODES};
+use lsapp::mime::MimeDatabase;
+use lsapp::mimeapps::{self, MimeApps, MimeAppsList};
+use lsapp::output::{self, shell_quote, Field, Format, Separator};
+use lsapp::search::Query;
+use lsapp::terminal::Terminal;
+use lsapp::template::Source;
+use serde_json::{json, Map, Value};
+
+const DEFAULT_COLUMNS: &[&str] = &["name", "comment", "path"];
+
+/// Columns shown by default in a launcher menu
+const MENU_COLUMNS: &[&str] = &["name"];
+
+/// Names of the built-in columns
+const COLUMNS: &[&str] = &[
+    "name", "generic-name", "comment", "path", "filename", "desktop-id", "source-dir", "type",
+    "exec", "try-exec", "tryexec", "working-dir", "terminal", "categories", "keywords", "mimetype",
+    "icon", "icon-path", "actions", "startup-wm-class", "no-display",
 ];
 
 #[derive(Debug, Clone, Copy)]
 enum Column<'a> {
     Name { lang: Option<&'a str> },
+    GenericName { lang: Option<&'a str> },
     Comment { lang: Option<&'a str> },
     Path,
     Filename { with_ext: bool },
+    DesktopId { sources: &'a [PathBuf] },
+    SourceDir { sources: &'a [PathBuf] },
+    Type,
+    Exec,
+    /// The program named by `TryExec`
+    TryExecProgram,
+    /// Whether the program named by `TryExec` is installed
+    TryExec { check: bool },
+    WorkingDir,
+    Terminal,
     Categories,
+    Keywords { lang: Option<&'a str> },
+    MimeType,
     Icon,
+    /// The file the `Icon` key resolves to in the icon theme
+    IconPath { icons: Option<&'a IconFinder> },
+    Actions,
+    StartupWmClass,
+    NoDisplay,
+    /// Any key of any group, named on the command line by `spec`. A `locale` given with the key
+    /// is looked up exactly, while `--lang` falls back like it does for `name`.
+    Key { spec: &'a str, group: &'a str, key: &'a str, locale: Option<&'a str>, lang: Option<&'a str> },
 }
 
 impl<'a> FromStr for Column<'a> {
@@ -34,45 +112,507 @@ impl<'a> FromStr for Column<'a> {
     fn from_str(s: &str) -> Result<Column<'a>> {
         match s.to_lowercase().as_str() {
             "name" => Ok(Column::Name { lang: None }),
+            "generic-name" => Ok(Column::GenericName { lang: None }),
             "comment" => Ok(Column::Comment { lang: None }),
             "path" => Ok(Column::Path),
             "filename" => Ok(Column::Filename { with_ext: false }),
+            "desktop-id" => Ok(Column::DesktopId { sources: &[] }),
+            "source-dir" => Ok(Column::SourceDir { sources: &[] }),
+            "type" => Ok(Column::Type),
+            "exec" => Ok(Column::Exec),
+            "try-exec" => Ok(Column::TryExecProgram),
+            "tryexec" => Ok(Column::TryExec { check: true }),
+            "working-dir" => Ok(Column::WorkingDir),
+            "terminal" => Ok(Column::Terminal),
             "categories" => Ok(Column::Categories),
+            "keywords" => Ok(Column::Keywords { lang: None }),
+            "mimetype" => Ok(Column::MimeType),
             "icon" => Ok(Column::Icon),
+            "icon-path" => Ok(Column::IconPath { icons: None }),
+            "actions" => Ok(Column::Actions),
+            "startup-wm-class" => Ok(Column::StartupWmClass),
+            "no-display" => Ok(Column::NoDisplay),
             _ => Err(AppError::InvalidColumn(s.into()).into()),
         }
     }
 }
 
-#[derive(Debug)]
-enum Separator {
-    Comma,
-    Tab,
-    Spaces,
+/// Where the value of a setting came from
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Origin {
+    CommandLine,
+    Environment,
+    Config,
+    Default,
+}
+
+impl Origin {
+    fn name(self) -> &'static str {
+        match self {
+            Origin::CommandLine => "command line",
+            Origin::Environment => "environment",
+            Origin::Config => "config",
+            Origin::Default => "default",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Setting<T> {
+    value: T,
+    origin: Origin,
+}
+
+impl<T> Setting<T> {
+    /// The first of the command line, the environment or the config file to give a value, or
+    /// the default
+    fn resolve(given: Option<(T, Origin)>, config: Option<T>, default: T) -> Setting<T> {
+        match (given, config) {
+            (Some((value, origin)), _) => Setting { value, origin },
+            (None, Some(value)) => Setting { value, origin: Origin::Config },
+            (None, None) => Setting { value: default, origin: Origin::Default },
+        }
+    }
+}
+
+/// The settings that can be set in the config file, resolved against the command line and the
+/// environment
+#[derive(Debug, Clone)]
+struct Settings {
+    sources: Setting<Vec<String>>,
+    columns: Setting<Vec<String>>,
+    separator: Setting<Separator>,
+    quote: Setting<bool>,
+    lang: Setting<Option<String>>,
+    filter: Setting<Option<String>>,
+    sort: Setting<Vec<String>>,
+    unique: Setting<Vec<String>>,
+    terminal: Setting<Option<String>>,
+    finder: Setting<String>,
+}
+
+impl Settings {
+    fn resolve(matches: &ArgMatches, config: &config::Settings) -> Settings {
+        let strings = |defaults: &[&str]| defaults.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        let values = |name| arg_values(matches, name);
+        let value = |name| arg_values(matches, name)
+            .map(|(values, origin)| (values.into_iter().next(), origin));
+
+        // `terminal` and `finder` belong to subcommands, but `config show` still reports them
+        let launcher = ["run", "pick", "open"].iter()
+            .find_map(|name| matches.subcommand_matches(name));
+        let launcher_value = |name: &str, var: &str| match launcher {
+            Some(launcher) => arg_values(launcher, name)
+                .and_then(|(values, origin)| Some((values.into_iter().next()?, origin))),
+            None => env::var(var).ok().map(|value| (value, Origin::Environment)),
+        };
+
+        let config_sources = match (&config.sources, config.extra_sources.is_empty()) {
+            (None, true) => None,
+            (sources, _) => Some(sources.clone()
+                .unwrap_or_else(default_sources)
+                .into_iter()
+                .chain(config.extra_sources.iter().cloned())
+                .collect()),
+        };
+
+        let separator = [("comma", Separator::Comma), ("tab", Separator::Tab), ("spaces", Separator::Spaces)]
+            .iter()
+            .find(|(name, _)| matches.is_present(name))
+            .map(|(_, separator)| (*separator, Origin::CommandLine));
+
+        let quote = if matches.is_present("quote") {
+            Some((true, Origin::CommandLine))
+        } else if matches.is_present("no_quote") {
+            Some((false, Origin::CommandLine))
+        } else {
+            None
+        };
+
+        Settings {
+            sources: Setting::resolve(values("sources"), config_sources, default_sources()),
+            columns: Setting::resolve(values("column"), config.columns.clone(),
+                strings(if matches.is_present("mode") { MENU_COLUMNS } else { DEFAULT_COLUMNS })),
+            separator: Setting::resolve(separator, config.separator, Separator::Tab),
+            quote: Setting::resolve(quote, config.quote, false),
+            lang: Setting::resolve(value("lang"), Some(config.lang.clone()).filter(Option::is_some), None),
+            filter: Setting::resolve(value("filter"), Some(config.filter.clone()).filter(Option::is_some), None),
+            sort: Setting::resolve(values("sort"), config.sort.clone(), vec![]),
+            unique: Setting::resolve(values("unique"), config.unique.clone(), vec![]),
+            terminal: Setting::resolve(launcher_value("terminal", "LSAPP_TERMINAL").map(|(v, o)| (Some(v), o)),
+                Some(config.terminal.clone()).filter(Option::is_some), None),
+            finder: Setting::resolve(launcher_value("finder", "LSAPP_FINDER"), config.finder.clone(), "fzf".into()),
+        }
+    }
+
+    /// Print the settings as they would be written in the config file, each followed by where
+    /// its value came from
+    fn print(&self, config_path: Option<&Path>, profile: Option<&str>) {
+        let list = |values: &[String]| Some(toml::Value::Array(values.iter().cloned().map(toml::Value::String).collect()));
+        let string = |value: &str| Some(toml::Value::String(value.into()));
+        let optional = |value: &Option<String>| value.clone().map(toml::Value::String);
+        let separator = match self.separator.value {
+            Separator::Comma => "comma",
+            Separator::Tab => "tab",
+            Separator::Spaces => "spaces",
+        };
+
+        let rows = [
+            ("sources", list(&self.sources.value), self.sources.origin),
+            ("columns", list(&self.columns.value), self.columns.origin),
+            ("separator", string(separator), self.separator.origin),
+            ("quote", Some(toml::Value::Boolean(self.quote.value)), self.quote.origin),
+            ("lang", optional(&self.lang.value), self.lang.origin),
+            ("filter", optional(&self.filter.value), self.filter.origin),
+            ("sort", list(&self.sort.value), self.sort.origin),
+            ("unique", list(&self.unique.value), self.unique.origin),
+            ("terminal", optional(&self.terminal.value), self.terminal.origin),
+            ("finder", string(&self.finder.value), self.finder.origin),
+        ];
+
+        match config_path {
+            Some(path) => println!("# config file: {}", path.display()),
+            None => println!("# config file: none, since neither XDG_CONFIG_HOME nor HOME is set"),
+        }
+
+        if let Some(profile) = profile {
+            println!("# profile: {}", profile);
+        }
+
+        println!();
+        let lines = rows.iter()
+            .map(|(name, value, origin)| {
+                // Settings without a value can't be written in TOML, so they're commented out
+                let line = match value {
+                    Some(value) => format!("{} = {}", name, value),
+                    None => format!("# {} is not set", name),
+                };
+
+                (line, origin.name())
+            })
+            .collect::<Vec<_>>();
+
+        let width = lines.iter().map(|(line, _)| line.len()).max().unwrap_or(0);
+        for (line, origin) in lines {
+            println!("{:<width$}  # {}", line, origin, width = width);
+        }
+    }
+}
+
+/// The values of an argument, if it was given on the command line or in its environment variable
+fn arg_values(matches: &ArgMatches, name: &str) -> Option<(Vec<String>, Origin)> {
+    let values = matches.values_of(name)?
+        .map(String::from)
+        .collect();
+
+    let origin = if matches.occurrences_of(name) > 0 { Origin::CommandLine } else { Origin::Environment };
+    Some((values, origin))
+}
+
+/// Options from the command line that change how columns are rendered
+#[derive(Debug, Clone, Copy)]
+struct ColumnOptions<'a> {
+    lang: Option<&'a str>,
+    with_ext: bool,
+    check_tryexec: bool,
+    /// Source directories with `~` expanded, for columns derived from where a file was found
+    sources: &'a [PathBuf],
+    icons: &'a IconFinder,
+}
+
+impl<'a> Column<'a> {
+    /// Parse a column given on the command line. Besides the built-in names, a key can be
+    /// selected with `key:KEY`, `GROUP/KEY` or `KEY[LOCALE]`, and those forms can be combined.
+    fn parse(spec: &'a str) -> Result<Column<'a>> {
+        let (name, lang) = match spec.find('[') {
+            Some(idx) if spec.ends_with(']') => (&spec[..idx], Some(&spec[idx + 1..spec.len() - 1])),
+            Some(..) => return Err(AppError::InvalidColumn(spec.into()).into()),
+            None => (spec, None),
+        };
+
+        Column::resolve(spec, name, lang)
+            .ok_or_else(|| AppError::InvalidColumn(spec.into()).into())
+    }
+
+    /// Resolve a column name with an optional locale. A built-in name given a locale selects
+    /// the key behind that column, so that each locale gets its own column.
+    fn resolve(spec: &'a str, name: &'a str, lang: Option<&'a str>) -> Option<Column<'a>> {
+        if let Some(key) = name.strip_prefix("key:") {
+            return Some(Column::for_key(spec, key, lang));
+        }
+
+        if name.contains('/') {
+            return Some(Column::for_key(spec, name, lang));
+        }
+
+        match (Column::from_str(&name.replace('_', "-")), lang) {
+            (Ok(column), None) => Some(column),
+            (Ok(column), Some(..)) => Some(Column::for_key(spec, column.key().unwrap_or(name), lang)),
+            (Err(..), Some(..)) => Some(Column::for_key(spec, name, lang)),
+            (Err(..), None) => None,
+        }
+    }
+
+    /// A column for a key, which is in the `[Desktop Entry]` group unless it's given as
+    /// `GROUP/KEY`
+    fn for_key(spec: &'a str, key: &'a str, locale: Option<&'a str>) -> Column<'a> {
+        match key.rfind('/') {
+            Some(idx) => Column::Key { spec, group: &key[..idx], key: &key[idx + 1..], locale, lang: None },
+            None => Column::Key { spec, group: DESKTOP_ENTRY, key, locale, lang: None },
+        }
+    }
+
+    /// Apply the command line options to a column parsed from its name
+    fn with_options(self, options: &ColumnOptions<'a>) -> Column<'a> {
+        match self {
+            Column::Name { .. } => Column::Name { lang: options.lang },
+            Column::GenericName { .. } => Column::GenericName { lang: options.lang },
+            Column::Comment { .. } => Column::Comment { lang: options.lang },
+            Column::Keywords { .. } => Column::Keywords { lang: options.lang },
+            Column::Filename { .. } => Column::Filename { with_ext: options.with_ext },
+            Column::DesktopId { .. } => Column::DesktopId { sources: options.sources },
+            Column::SourceDir { .. } => Column::SourceDir { sources: options.sources },
+            Column::TryExec { .. } => Column::TryExec { check: options.check_tryexec },
+            Column::IconPath { .. } => Column::IconPath { icons: Some(options.icons) },
+            Column::Key { spec, group, key, locale, .. } => Column::Key { spec, group, key, locale, lang: options.lang },
+            column => column,
+        }
+    }
+
+    fn name(&self) -> &'a str {
+        match self {
+            Column::Name { .. } => "name",
+            Column::GenericName { .. } => "generic-name",
+            Column::Comment { .. } => "comment",
+            Column::Path => "path",
+            Column::Filename { .. } => "filename",
+            Column::DesktopId { .. } => "desktop-id",
+            Column::SourceDir { .. } => "source-dir",
+            Column::Type => "type",
+            Column::Exec => "exec",
+            Column::TryExecProgram => "try-exec",
+            Column::TryExec { .. } => "tryexec",
+            Column::WorkingDir => "working-dir",
+            Column::Terminal => "terminal",
+            Column::Categories => "categories",
+            Column::Keywords { .. } => "keywords",
+            Column::MimeType => "mimetype",
+            Column::Icon => "icon",
+            Column::IconPath { .. } => "icon-path",
+            Column::Actions => "actions",
+            Column::StartupWmClass => "startup-wm-class",
+            Column::NoDisplay => "no-display",
+            Column::Key { spec, .. } => spec,
+        }
+    }
+
+    /// The key of the `[Desktop Entry]` group that a built-in column shows
+    fn key(&self) -> Option<&'static str> {
+        match self {
+            Column::Name { .. } => Some("Name"),
+            Column::GenericName { .. } => Some("GenericName"),
+            Column::Comment { .. } => Some("Comment"),
+            Column::Type => Some("Type"),
+            Column::Exec => Some("Exec"),
+            Column::TryExecProgram => Some("TryExec"),
+            Column::WorkingDir => Some("Path"),
+            Column::Terminal => Some("Terminal"),
+            Column::Categories => Some("Categories"),
+            Column::Keywords { .. } => Some("Keywords"),
+            Column::MimeType => Some("MimeType"),
+            Column::Icon => Some("Icon"),
+            Column::Actions => Some("Actions"),
+            Column::StartupWmClass => Some("StartupWMClass"),
+            Column::NoDisplay => Some("NoDisplay"),
+            _ => None,
+        }
+    }
+
+    /// The key holding this column's value, for columns that can be localized
+    fn localized_key(&self) -> Option<&'static str> {
+        match self {
+            Column::Name { .. } | Column::GenericName { .. } | Column::Comment { .. } => self.key(),
+            _ => None,
+        }
+    }
+
+    fn field(&self, entry: &DesktopEntry) -> Field {
+        let text = |value: Option<String>| value.map_or(Field::Empty, Field::Text);
+        let list = |value: Option<Vec<String>>| value.map_or(Field::Empty, Field::List);
+
+        match *self {
+            Column::Name { lang } => text(entry.get_localized("Name", lang)),
+            Column::GenericName { lang } => text(entry.get_localized("GenericName", lang)),
+            Column::Comment { lang } => text(entry.get_localized("Comment", lang)),
+            Column::Path => Field::Text(entry.path.display().to_string()),
+            Column::Filename { with_ext } => {
+                let name = if with_ext {
+                    entry.path.file_name()
+                } else {
+                    entry.path.file_stem()
+                };
+
+                text(name.map(|name| name.to_string_lossy().into_owned()))
+            },
+            Column::DesktopId { sources } => Field::Text(lsapp::desktop_id(&entry.path, sources)),
+            Column::SourceDir { sources } => text(lsapp::source_dir(&entry.path, sources)
+                .map(|dir| dir.display().to_string())),
+            Column::Type => text(entry.get("Type")),
+            Column::Exec => text(entry.get("Exec")),
+            Column::TryExecProgram => text(entry.get("TryExec")),
+            Column::TryExec { check: true } => Field::Text(entry.try_exec().status().into()),
+            Column::TryExec { check: false } => Field::Text("unchecked".into()),
+            Column::WorkingDir => text(entry.get("Path")),
+            Column::Terminal => Field::Bool(entry.get_bool("Terminal").unwrap_or(false)),
+            Column::Categories => list(entry.get_list("Categories")),
+            Column::Keywords { lang } => list(entry.get_localized_list("Keywords", lang)),
+            Column::MimeType => list(entry.get_list("MimeType")),
+            Column::Icon => text(entry.get("Icon")),
+            Column::IconPath { icons } => text(icons
+                .zip(entry.get("Icon"))
+                .and_then(|(icons, icon)| icons.find(&icon))
+                .map(|path| path.display().to_string())),
+            Column::Actions => list(entry.get_list("Actions")),
+            Column::StartupWmClass => text(entry.get("StartupWMClass")),
+            Column::NoDisplay => Field::Bool(entry.get_bool("NoDisplay").unwrap_or(false)),
+            Column::Key { group, key, locale: Some(locale), .. } => text(entry.group(group)
+                .and_then(|group| group.get_raw(key, Some(locale)))
+                .map(lsapp::entry::unescape)),
+            Column::Key { group, key, locale: None, lang, .. } => text(entry.group(group)
+                .and_then(|group| group.get_localized(key, lang))),
+        }
+    }
+
+    fn value(&self, entry: &DesktopEntry) -> String {
+        self.field(entry).to_text()
+    }
+}
+
+/// Parse an icon size given as `SIZE` or `SIZE@SCALE`
+fn parse_icon_size(spec: &str) -> Result<(u32, u32)> {
+    let (size, scale) = match spec.find('@') {
+        Some(idx) => (&spec[..idx], &spec[idx + 1..]),
+        None => (spec, "1"),
+    };
+
+    match (size.parse::<u32>(), scale.parse::<u32>()) {
+        (Ok(size), Ok(scale)) if size > 0 && scale > 0 => Ok((size, scale)),
+        _ => Err(AppError::InvalidIconSize(spec.into()).into()),
+    }
+}
+
+/// Parse the columns given on the command line or in the config file
+fn parse_columns<'a>(specs: &'a [String], options: &ColumnOptions<'a>) -> Result<Vec<Column<'a>>> {
+    specs.iter()
+        .map(|spec| Column::parse(spec).map(|column| column.with_options(options)))
+        .collect()
+}
+
+/// Resolve the sources named in a template or filter to columns. Unlike `-d`, these can name
+/// any key of the `[Desktop Entry]` group directly.
+fn source_columns<'a>(sources: &'a [Source], options: &ColumnOptions<'a>) -> Vec<Column<'a>> {
+    sources.iter()
+        .map(|source| {
+            let (name, lang) = (source.name.as_str(), source.locale.as_deref());
+            Column::resolve(name, name, lang)
+                .unwrap_or_else(|| Column::for_key(name, name, lang))
+                .with_options(options)
+        })
+        .collect()
+}
+
+/// A parsed `--format-template` with its placeholders resolved
+struct FormatTemplate<'a> {
+    template: &'a Template,
+    sources: Vec<Column<'a>>,
+}
+
+impl<'a> FormatTemplate<'a> {
+    fn new(template: &'a Template, options: &ColumnOptions<'a>) -> FormatTemplate<'a> {
+        FormatTemplate { template, sources: source_columns(template.sources(), options) }
+    }
+
+    fn render(&self, entry: &DesktopEntry) -> String {
+        self.template.render(|source| self.sources[source.idx].field(entry))
+    }
+}
+
+/// A parsed `--filter` with its operands resolved
+struct EntryFilter<'a> {
+    filter: &'a Filter,
+    sources: Vec<Column<'a>>,
+}
+
+impl<'a> EntryFilter<'a> {
+    fn new(filter: &'a Filter, options: &ColumnOptions<'a>) -> EntryFilter<'a> {
+        EntryFilter { filter, sources: source_columns(filter.sources(), options) }
+    }
+
+    fn matches(&self, entry: &DesktopEntry) -> bool {
+        self.filter.matches(|source| self.sources[source.idx].field(entry))
+    }
 }
 
 #[derive(Error, Debug)]
 pub enum AppError {
-    #[error("unsupported column type `{0}`")]
+    #[error("unsupported column `{0}`; use one of {}, or a key as `key:KEY`, `GROUP/KEY` or `KEY[LOCALE]`",
+        COLUMNS.join(", "))]
     InvalidColumn(String),
 
     #[error("{0}")]
-    ArgError(clap::Error)
+    ArgError(String),
+
+    #[error("invalid icon size `{0}`; use SIZE or SIZE@SCALE, like 48 or 24@2")]
+    InvalidIconSize(String),
+
+    #[error("cannot determine a cache directory; set XDG_CACHE_HOME or HOME")]
+    NoCacheDir,
+
+    #[error("no application with desktop file ID `{0}`")]
+    NoSuchEntry(String),
+
+    #[error("no application is listed as `{0}`")]
+    NoSuchItem(String),
+
+    #[error("no application opens {0}")]
+    NoHandler(String),
+
+    #[error("{0} does not exist")]
+    NoSuchFile(String),
+
+    #[error("invalid MIME type `{0}`; use TYPE/SUBTYPE, like text/plain")]
+    InvalidMimeType(String),
+
+    #[error("{id} doesn't list {mime} in its MimeType key; use --force to set it anyway")]
+    UndeclaredMimeType { id: String, mime: String },
+
+    #[error("out of date: {0}; run `lsapp update-mime-cache` to update")]
+    StaleMimeCache(String),
+
+    #[error("cannot determine a config directory; set XDG_CONFIG_HOME or HOME")]
+    NoConfigDir,
+
+    #[error("{0} runs in a terminal, but no terminal emulator was found; set --terminal or TERMINAL")]
+    NoTerminal(String),
+
+    #[error("{0} failed with {1}")]
+    FinderFailed(String, ExitStatus),
 }
 
-fn main() -> Result<()> {
-    let matches = clap_app!(lsapp =>
+fn app() -> App<'static> {
+    clap_app!(lsapp =>
         (version: "0.1")
         (author: "Carson Myers <carson@myers.se>")
         (about: "List installed applications scanned from .desktop files")
-        (@arg sources: -S --sources +takes_value +multiple +use_delimiter
+        (@arg profile: -P --profile +takes_value env("LSAPP_PROFILE")
+            "Use the settings of a profile from the config file")
+        (@arg sources: -S --sources +takes_value +multiple_occurrences +multiple_values +use_delimiter +require_delimiter
             env("LSAPP_SOURCES")
-            default_value(&DEFAULT_SOURCES.join(","))
-            "Source directories for application .desktop files")
-        (@arg column: -d --data +takes_value +multiple +use_delimiter
-            possible_values(&["name", "comment", "path", "filename", "categories", "icon"])
-            default_value("name,comment,path")
-            "Columns of data to include in the output")
+            "Source directories for application .desktop files, by default the system and user \
+             application directories")
+        (@arg column: -d --data +takes_value +multiple_occurrences +multiple_values +use_delimiter +require_delimiter
+            "Columns of data to include in the output: a built-in column, or any key as `key:KEY`, \
+             `GROUP/KEY` or `KEY[LOCALE]` [default: name,comment,path]")
         (@arg lang: -l --lang +takes_value
             "Language to use for name and comment, if available")
         (@arg ext: -x --("with-ext") "Includes extension in filename")
@@ -83,38 +623,1283 @@ fn main() -> Result<()> {
         (@arg spaces: -s --spaces conflicts_with_all(&["comma", "tab"])
             "Separate columns with spaces as padding")
         (@arg quote: -q --quote "Quote values in columns")
-    ).get_matches();
-
-    let lang = matches.value_of("lang");
-    let with_ext = matches.is_present("ext");
-
-    let _columns = matches.values_of_t("column")
-        .map_err(|err| AppError::ArgError(err))?
-        .iter()
-        .map(|v| match v {
-            Column::Name { .. } => Column::Name { lang },
-            Column::Comment { .. } => Column::Comment { lang },
-            Column::Filename { .. } => Column::Filename { with_ext },
-            _ => v.to_owned(),
+        (@arg no_quote: --("no-quote") conflicts_with("quote")
+            "Don't quote values, even when the config file says to")
+        (@arg print0: -('0') --print0 alias("null")
+            "End each entry with a NUL instead of a line break")
+        (@arg key: -k --key
+            "Start each line with the path of the .desktop file, for fzf's --with-nth to hide")
+        (@arg mode: -m --mode +takes_value possible_values(MODES) conflicts_with_all(&["key", "print0", "watch"])
+            "Print the listing as input for a launcher menu, with icons; given a chosen line, \
+             launch its entry instead")
+        (@arg selection: +hidden requires("mode")
+            "A line chosen from the menu, whose entry is launched")
+        (@arg format: -f --format +takes_value
+            possible_values(&["text", "json", "jsonl"])
+            default_value("text")
+            "Output format: separated text, a JSON array, or one JSON object per line")
+        (@arg template: -T --("format-template") +takes_value
+            "Print each entry with a template like '{name} ({generic_name|default:none})\\t{path}', \
+             instead of columns")
+        (@arg filter: -F --filter +takes_value
+            "Only show entries matching an expression like 'categories has Game && !terminal'")
+        (@arg sort: --sort +takes_value +multiple_occurrences +multiple_values +use_delimiter +require_delimiter +allow_hyphen_values
+            "Sort by these columns, ignoring case and accents the same way for every locale; \
+             prefix a column with `-` to sort it in descending order")
+        (@arg unique: --unique +takes_value +multiple_occurrences +multiple_values +use_delimiter +require_delimiter
+            "Only show the first entry for each distinct value of these columns")
+        (@arg icon_size: --("icon-size") +takes_value
+            default_value("48")
+            "Size of the icons found for the icon-path column, as SIZE or SIZE@SCALE")
+        (@arg icon_theme: --("icon-theme") +takes_value
+            default_value("hicolor")
+            "Icon theme the icon-path column looks in before the themes it inherits from")
+        (@arg all_locales: --("all-locales")
+            "In JSON output, include every locale of localized columns as an object")
+        (@arg no_tryexec: --("no-tryexec")
+            "Show entries without checking that their TryExec program is installed")
+        (@arg jobs: -j --jobs +takes_value
+            default_value("0")
+            "Number of threads used to read .desktop files, or 0 for one per CPU")
+        (@arg no_cache: --("no-cache")
+            "Read every .desktop file instead of using the parse cache")
+        (@arg watch: -w --watch
+            "Keep running and print the listing again whenever a .desktop file changes")
+        (@arg events: --events requires("watch")
+            "While watching, print JSON add/update/remove events instead of the full listing")
+        (@arg debounce: --debounce +takes_value
+            default_value("250")
+            "Milliseconds to wait for a burst of changes to settle before printing")
+        (@subcommand search =>
+            (about: "List the entries matching a search, best match first")
+            (@arg query: +required +multiple_values
+                "Words to look for in names, generic names, keywords, comments and commands")
+            (@arg score: --score "Show the score of each entry before its columns")
+            (@arg limit: -n --limit +takes_value "Show at most this many entries"))
+        (@subcommand run =>
+            (about: "Launch an application the way a desktop environment would")
+            (@arg id: +required
+                "Desktop file ID of the application, like firefox.desktop, or the path of its .desktop file")
+            (@arg targets: +multiple_values "Files or URLs to open with the application")
+            (@arg dry_run: --("dry-run")
+                "Print the commands that would be run, quoted for a shell, instead of running them")
+            (@arg terminal: --terminal +takes_value env("LSAPP_TERMINAL")
+                "Terminal emulator for applications with Terminal=true, like 'alacritty' or 'foot -e'"))
+        (@subcommand show =>
+            (about: "Show every group and key of an application, where it came from, and its command")
+            (@arg id: +required
+                "Desktop file ID of the application, or the path of its .desktop file")
+            (@arg json: --json "Print the same details as a JSON object"))
+        (@subcommand preview =>
+            (about: "Describe an application in a few lines, for fzf's --preview")
+            (@arg id: +required
+                "Desktop file ID of the application, or the path of its .desktop file"))
+        (@subcommand pick =>
+            (about: "Choose an application with fzf or skim and launch it")
+            (long_about: "Choose an application with fzf or skim and launch it.\n\n\
+                The listing is given to the finder keyed by path, with the key hidden and \
+                `lsapp preview` describing the highlighted entry. Columns, templates, filters \
+                and sorting apply as they do for the listing.")
+            (@arg finder: --finder +takes_value env("LSAPP_FINDER")
+                "Finder to run, which must accept fzf's --delimiter, --with-nth and --preview [default: fzf]")
+            (@arg dry_run: --("dry-run")
+                "Print the commands that would be run, quoted for a shell, instead of running them")
+            (@arg terminal: --terminal +takes_value env("LSAPP_TERMINAL")
+                "Terminal emulator for applications with Terminal=true, like 'alacritty' or 'foot -e'"))
+        (@subcommand ("for-mime") =>
+            (about: "List the applications that open a MIME type, most preferred first")
+            (long_about: "List the applications that open a MIME type, most preferred first.\n\n\
+                Applications are found by their MimeType key and mimeinfo.cache, with the \
+                associations added, removed and made default by mimeapps.list in \
+                $XDG_CONFIG_HOME, $XDG_CONFIG_DIRS and the source directories. Applications for \
+                aliases of the type count as its own, and those for the types it's a kind of, \
+                like text/plain for text/x-csrc, follow them.")
+            (@arg mime_type: +required "MIME type, like text/plain or x-scheme-handler/https")
+            (@arg default: --default "Only show the application chosen to open the type"))
+        (@subcommand open =>
+            (about: "Open a file or URL with the default application for its type")
+            (long_about: "Open a file or URL with the default application for its type.\n\n\
+                The type of a file is found from its name with the globs of the shared-mime-info \
+                database, or else from its contents with the database's magic. A URL is opened by \
+                the handler of its scheme, like x-scheme-handler/https. The application is the \
+                one `lsapp default get` shows for the type.")
+            (@arg target: +required "File or URL to open")
+            (@arg dry_run: --("dry-run")
+                "Print the commands that would be run, quoted for a shell, instead of running them")
+            (@arg terminal: --terminal +takes_value env("LSAPP_TERMINAL")
+                "Terminal emulator for applications with Terminal=true, like 'alacritty' or 'foot -e'"))
+        (@subcommand default =>
+            (about: "Show or change the application that opens a MIME type")
+            (long_about: "Show or change the application that opens a MIME type.\n\n\
+                Changes are written to $XDG_CONFIG_HOME/mimeapps.list, keeping its comments and \
+                other associations.")
+            (@setting SubcommandRequiredElseHelp)
+            (@subcommand get =>
+                (about: "Print the desktop file ID of the application that opens a type")
+                (@arg mime_type: +required "MIME type, like text/plain or x-scheme-handler/https"))
+            (@subcommand set =>
+                (about: "Make an application the default for a type")
+                (@arg mime_type: +required "MIME type, like text/plain or x-scheme-handler/https")
+                (@arg id: +required "Desktop file ID of the application, like firefox.desktop")
+                (@arg force: --force
+                    "Set the default even if the application isn't installed or doesn't list the type"))
+            (@subcommand unset =>
+                (about: "Remove the default for a type set in $XDG_CONFIG_HOME/mimeapps.list")
+                (@arg mime_type: +required "MIME type, like text/plain or x-scheme-handler/https")))
+        (@subcommand ("update-mime-cache") =>
+            (about: "Write the mimeinfo.cache of application directories, like update-desktop-database")
+            (@arg dir: "Directory of .desktop files to scan, instead of every source directory")
+            (@arg check: --check "Only check that each mimeinfo.cache is up to date, failing if one isn't"))
+        (@subcommand cache =>
+            (about: "Manage the cache of parsed .desktop files")
+            (@setting SubcommandRequiredElseHelp)
+            (@subcommand rebuild =>
+                (about: "Discard the cache and re-read every source directory"))
+            (@subcommand clear =>
+                (about: "Delete the cache")))
+        (@subcommand config =>
+            (about: "Inspect the settings from the config file")
+            (long_about: "Inspect the settings from the config file.\n\n\
+                Settings are read from $XDG_CONFIG_HOME/lsapp/config.toml, where a [profiles.NAME] \
+                table holds settings used with --profile NAME. The command line takes precedence \
+                over the environment, which takes precedence over the config file.")
+            (@setting SubcommandRequiredElseHelp)
+            (@subcommand show =>
+                (about: "Print the settings in effect, and where each one came from")))
+        (@subcommand completions =>
+            (about: "Print a completion script for bash, zsh or fish")
+            (long_about: "Print a completion script for bash, zsh or fish.\n\n\
+                Besides flags, the scripts complete desktop file IDs, column names and the X- keys \
+                found in the scanned entries. Load one with `source <(lsapp completions bash)`, \
+                `source (lsapp completions fish | psub)`, or by saving the zsh script as `_lsapp` \
+                in a directory on $fpath.")
+            (@arg shell: +required possible_values(SHELLS) "Shell to complete in"))
+        (@subcommand complete =>
+            (about: "List completions for the words of a command line, for the completion scripts")
+            (@setting Hidden)
+            (@arg shell: --shell +takes_value +required possible_values(SHELLS) "Shell to format completions for")
+            (@arg words: +multiple_values +last +allow_hyphen_values "Words after `lsapp`, the last being completed"))
+    )
+}
+
+fn main() -> Result<()> {
+    let matches = app().get_matches();
+
+    let config_path = Config::default_path();
+    let config = match &config_path {
+        Some(path) => Config::load(path)
+            .wrap_err_with(|| format!("cannot load {}", path.display()))?,
+        None => Config::default(),
+    };
+    let settings = Settings::resolve(&matches, &config.settings(matches.value_of("profile"))?);
+
+    if let Some(config_matches) = matches.subcommand_matches("config") {
+        if config_matches.subcommand_matches("show").is_some() {
+            settings.print(config_path.as_deref(), matches.value_of("profile"));
+        }
+
+        return Ok(());
+    }
+
+    let sources = settings.sources.value.iter()
+        .map(String::as_str)
+        .collect::<Vec<&str>>();
+    let expanded_sources = lsapp::expand_sources(&sources);
+
+    let check_tryexec = !matches.is_present("no_tryexec");
+    let (icon_size, icon_scale) = parse_icon_size(matches.value_of("icon_size").unwrap_or_default())?;
+    let icons = IconFinder::new(matches.value_of("icon_theme").unwrap_or_default(), icon_size, icon_scale);
+    let options = ColumnOptions {
+        lang: settings.lang.value.as_deref(),
+        with_ext: matches.is_present("ext"),
+        check_tryexec,
+        sources: &expanded_sources,
+        icons: &icons,
+    };
+    let jobs = matches.value_of_t("jobs")
+        .map_err(|err| AppError::ArgError(err.to_string()))?;
+
+    let columns = parse_columns(&settings.columns.value, &options)?;
+
+    let sort = settings.sort.value.iter()
+        .map(|v| {
+            let (spec, descending) = match v.strip_prefix('-') {
+                Some(spec) => (spec, true),
+                None => (v.as_str(), false),
+            };
+
+            Column::parse(spec).map(|column| (column.with_options(&options), descending))
         })
-        .collect::<Vec<Column>>();
-
-    let _separator = if matches.is_present("comma") {
-        Separator::Comma
-    } else if matches.is_present("tab") {
-        Separator::Tab
-    } else if matches.is_present("spaces") {
-        Separator::Spaces
+        .collect::<Result<Vec<_>>>()?;
+    let filter = settings.filter.value.as_deref()
+        .map(Filter::parse)
+        .transpose()?;
+    let filter = filter.as_ref()
+        .map(|filter| EntryFilter::new(filter, &options));
+    let order = Order { filter, sort, unique: parse_columns(&settings.unique.value, &options)?, sources: &expanded_sources };
+
+    let separator = settings.separator.value;
+    let quote = settings.quote.value;
+    let null = matches.is_present("print0");
+    let key = matches.is_present("key");
+    let format = match matches.value_of("format") {
+        Some("json") => Format::Json,
+        Some("jsonl") => Format::JsonLines,
+        _ => Format::Text,
+    };
+    let all_locales = matches.is_present("all_locales");
+    let template = matches.value_of("template")
+        .map(Template::parse)
+        .transpose()?;
+    let template = template.as_ref()
+        .map(|template| FormatTemplate::new(template, &options));
+    let listing = Listing {
+        columns: &columns,
+        template: template.as_ref(),
+        format,
+        separator,
+        quote,
+        null,
+        key,
+        all_locales,
+    };
+
+    let cache_path = if matches.is_present("no_cache") {
+        None
     } else {
-        Separator::Tab
+        Cache::default_path()
     };
 
-    let sources = matches.values_of("sources")
-        .map_or(vec![], |s| s.collect::<Vec<&str>>());
-    let files = lsapp::enumerate_desktop_files(sources);
-    for file in files {
-        lsapp::get_file_properties(file);
+    if let Some(cache_matches) = matches.subcommand_matches("cache") {
+        let cache_path = Cache::default_path()
+            .ok_or(AppError::NoCacheDir)?;
+
+        Cache::clear(&cache_path)?;
+        if cache_matches.subcommand_matches("rebuild").is_some() {
+            let mut cache = Cache::default();
+            cache.scan(&sources, jobs)?;
+            cache.save(&cache_path)?;
+        }
+
+        return Ok(());
+    }
+
+    if let Some(show_matches) = matches.subcommand_matches("show") {
+        let id = show_matches.value_of("id").unwrap_or_default();
+        let details = Details::find(id, &expanded_sources)?;
+
+        if show_matches.is_present("json") {
+            println!("{}", serde_json::to_string_pretty(&details.to_json())?);
+        } else {
+            details.print();
+        }
+
+        return Ok(());
+    }
+
+    if let Some(update_matches) = matches.subcommand_matches("update-mime-cache") {
+        let dirs = match update_matches.value_of("dir") {
+            Some(dir) => vec![PathBuf::from(dir)],
+            None => expanded_sources.iter().filter(|dir| dir.is_dir()).cloned().collect(),
+        };
+
+        return update_mime_cache(&dirs, update_matches.is_present("check"));
+    }
+
+    if let Some(completions_matches) = matches.subcommand_matches("completions") {
+        let shell = completions_matches.value_of_t::<Shell>("shell")
+            .map_err(|err| AppError::ArgError(err.to_string()))?;
+
+        print!("{}", shell.script());
+        return Ok(());
+    }
+
+    if let Some(complete_matches) = matches.subcommand_matches("complete") {
+        let entries = load_entries(&sources, jobs, cache_path.as_deref(), check_tryexec)?;
+        return complete(&app(), complete_matches, &entries, &expanded_sources);
+    }
+
+    // Entries are looked up among everything scanned, and only listings are filtered and sorted
+    let entries = load_entries(&sources, jobs, cache_path.as_deref(), check_tryexec)?;
+    if let Some(search_matches) = matches.subcommand_matches("search") {
+        let query = search_matches.values_of("query")
+            .map_or(vec![], |words| words.collect::<Vec<&str>>())
+            .join(" ");
+        let limit = search_matches.value_of("limit")
+            .map(|_| search_matches.value_of_t::<usize>("limit"))
+            .transpose()
+            .map_err(|err| AppError::ArgError(err.to_string()))?;
+
+        let query = Query::new(&query);
+        let mut ranked = order.apply(entries).into_iter()
+            .filter_map(|entry| query.score(&entry, options.lang).map(|score| (score, entry)))
+            .collect::<Vec<_>>();
+
+        // A stable sort keeps entries with the same score in the usual order
+        ranked.sort_by(|(a, _), (b, _)| b.cmp(a));
+        ranked.truncate(limit.unwrap_or(ranked.len()));
+
+        let (scores, entries): (Vec<u32>, Vec<DesktopEntry>) = ranked.into_iter().unzip();
+        let scores = if search_matches.is_present("score") { Some(&scores[..]) } else { None };
+        return listing.print_scored(&entries, scores);
+    }
+
+    if let Some(run_matches) = matches.subcommand_matches("run") {
+        let id = run_matches.value_of("id").unwrap_or_default();
+        let entry = find_entry(&entries, id, &expanded_sources)?;
+        let targets = run_matches.values_of("targets")
+            .map_or(vec![], |targets| targets.map(Target::new).collect());
+
+        let launcher = Launcher {
+            lang: options.lang,
+            terminal: settings.terminal.value.as_deref(),
+            dry_run: run_matches.is_present("dry_run"),
+        };
+
+        return launcher.launch(&entry, &targets);
+    }
+
+    if let Some(preview_matches) = matches.subcommand_matches("preview") {
+        let id = preview_matches.value_of("id").unwrap_or_default();
+        let entry = find_entry(&entries, id, &expanded_sources)?;
+
+        print_preview(&entry, options.lang, &expanded_sources);
+        return Ok(());
+    }
+
+    if let Some(pick_matches) = matches.subcommand_matches("pick") {
+        let launcher = Launcher {
+            lang: options.lang,
+            terminal: settings.terminal.value.as_deref(),
+            dry_run: pick_matches.is_present("dry_run"),
+        };
+
+        // The finder needs one entry per line, starting with the key it hands back
+        let listing = Listing { format: Format::Text, separator: Separator::Tab, null: false, key: true, quote: false, ..listing };
+        return pick(&order.apply(entries), &listing, &settings.finder.value, options.lang, &launcher);
+    }
+
+    if let Some(mime_matches) = matches.subcommand_matches("for-mime") {
+        let mime = mime_matches.value_of("mime_type").unwrap_or_default();
+        let mime_apps = MimeApps::load(&expanded_sources, &entries);
+        let lineage = MimeDatabase::load().lineage(mime);
+
+        let ids = if mime_matches.is_present("default") {
+            vec![mime_apps.default_for(&lineage).ok_or_else(|| AppError::NoHandler(mime.into()))?]
+        } else {
+            mime_apps.handlers_for(&lineage)
+        };
+
+        let handlers = ids.iter()
+            .map(|id| find_entry(&entries, id, &expanded_sources))
+            .collect::<Result<Vec<DesktopEntry>>>()?;
+        return listing.print(&handlers);
+    }
+
+    if let Some(open_matches) = matches.subcommand_matches("open") {
+        let target = Target::new(open_matches.value_of("target").unwrap_or_default());
+        if let Target::Path(path) = &target {
+            if !path.exists() {
+                return Err(AppError::NoSuchFile(path.display().to_string()).into());
+            }
+        }
+
+        let mime_db = MimeDatabase::load();
+        let mime = mime_db.type_for_target(&target);
+        let id = MimeApps::load(&expanded_sources, &entries).default_for(&mime_db.lineage(&mime))
+            .ok_or_else(|| AppError::NoHandler(mime.clone()))?;
+        let entry = find_entry(&entries, &id, &expanded_sources)?;
+
+        let launcher = Launcher {
+            lang: options.lang,
+            terminal: settings.terminal.value.as_deref(),
+            dry_run: open_matches.is_present("dry_run"),
+        };
+
+        return launcher.launch(&entry, &[target]);
+    }
+
+    if let Some(default_matches) = matches.subcommand_matches("default") {
+        return default(default_matches, &entries, &expanded_sources);
+    }
+
+    if let Some(mode) = matches.value_of("mode") {
+        let mode = mode.parse::<Mode>()?;
+        let entries = order.apply(entries);
+
+        // Each entry's text is rendered as it would be listed, ended by a NUL to keep them apart
+        let listing = Listing { format: Format::Text, null: true, key: false, ..listing };
+        let items = menu_items(mode, &entries, &listing, &icons, options.lang)?;
+
+        let selection = match matches.value_of("selection") {
+            Some(selection) => selection,
+            None => {
+                print_menu(mode, &items);
+                return Ok(());
+            },
+        };
+
+        // rofi hands back the path of the chosen entry, which tells apart entries listed alike
+        let entry = match env::var("ROFI_INFO") {
+            Ok(path) if mode == Mode::Rofi && !path.is_empty() => DesktopEntry::read(path)?,
+            _ => mode.position(&items, selection)
+                .map(|idx| entries[idx].clone())
+                .ok_or_else(|| AppError::NoSuchItem(mode.selected_text(selection).into()))?,
+        };
+
+        let launcher = Launcher {
+            lang: options.lang,
+            terminal: settings.terminal.value.as_deref(),
+            dry_run: false,
+        };
+
+        return launcher.launch(&entry, &[]);
+    }
+
+    let entries = order.apply(entries);
+    if !matches.is_present("watch") {
+        return listing.print(&entries);
+    }
+
+    let events = matches.is_present("events");
+    let debounce = matches.value_of_t("debounce")
+        .map_err(|err| AppError::ArgError(err.to_string()))?;
+    let debounce = Duration::from_millis(debounce);
+
+    let mut watcher = Watcher::new(&sources)?;
+    let mut entries = entries;
+
+    if events {
+        print_events(&[], &entries, &columns);
+    } else {
+        listing.print(&entries)?;
+        println!();
+    }
+
+    loop {
+        watcher.wait(debounce)?;
+
+        let updated = order.apply(load_entries(&sources, jobs, cache_path.as_deref(), check_tryexec)?);
+        if events {
+            print_events(&entries, &updated, &columns);
+        } else {
+            listing.print(&updated)?;
+            println!();
+        }
+
+        entries = updated;
+    }
+}
+
+fn load_entries(sources: &[&str], jobs: usize, cache_path: Option<&Path>, check_tryexec: bool) -> Result<Vec<DesktopEntry>> {
+    let entries = match cache_path {
+        Some(cache_path) => {
+            let mut cache = Cache::load(cache_path);
+            let entries = cache.scan(sources, jobs)?;
+
+            // A cache that can't be written only costs speed on the next run
+            let _ = cache.save(cache_path);
+            entries
+        },
+        None => {
+            let files = lsapp::enumerate_desktop_files(sources);
+            lsapp::read_desktop_entries(&files, jobs)?
+                .into_iter()
+                .filter_map(|entry| entry.ok())
+                .collect()
+        },
+    };
+
+    Ok(entries.into_iter()
+        .filter(|entry| !check_tryexec || entry.try_exec().is_available())
+        .collect())
+}
+
+/// The `applications` directory of each XDG data directory, the user's first so that their
+/// entries shadow the system's
+fn default_sources() -> Vec<String> {
+    lsapp::data_dirs().into_iter()
+        .map(|dir| dir.join("applications").to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Find the entry with a desktop file ID, which can be given without its `.desktop` suffix. The
+/// first entry wins, since earlier source directories shadow later ones. An ID containing a `/`
+/// is taken as the path of a .desktop file instead.
+fn find_entry(entries: &[DesktopEntry], id: &str, sources: &[PathBuf]) -> Result<DesktopEntry> {
+    if id.contains('/') {
+        return DesktopEntry::read(id);
+    }
+
+    entries.iter()
+        .find(|entry| {
+            let entry_id = lsapp::desktop_id(&entry.path, sources);
+            entry_id == id || entry_id.strip_suffix(".desktop") == Some(id)
+        })
+        .cloned()
+        .ok_or_else(|| AppError::NoSuchEntry(id.into()).into())
+}
+
+/// A key with its value for every locale, the unlocalized one first
+type KeyLocales<'a> = (&'a str, Vec<(Option<String>, String)>);
+
+/// Print the completions for the words of a command line. Values are completed from the scanned
+/// entries where that makes sense, and left to the shell's file completion otherwise.
+fn complete(app: &App, matches: &ArgMatches, entries: &[DesktopEntry], sources: &[PathBuf]) -> Result<()> {
+    let shell = matches.value_of_t::<Shell>("shell")
+        .map_err(|err| AppError::ArgError(err.to_string()))?;
+    let words = matches.values_of("words")
+        .map_or(vec![], |words| words.map(String::from).collect::<Vec<String>>());
+
+    let position = Position::locate(app, &words);
+    let candidates = match &position.context {
+        Context::Words(candidates) => candidates.clone(),
+        Context::Value(arg) => match arg.get_name() {
+            "column" | "unique" => column_candidates(entries),
+            // Descending sort columns are completed after their `-`
+            "sort" => match position.current.strip_prefix('-') {
+                Some(..) => column_candidates(entries).into_iter()
+                    .map(|candidate| Candidate { value: format!("-{}", candidate.value), ..candidate })
+                    .collect(),
+                None => column_candidates(entries),
+            },
+            "id" => id_candidates(entries, sources, true),
+            "query" => id_candidates(entries, sources, false),
+            "lang" => {
+                let mut locales = entries.iter()
+                    .filter_map(DesktopEntry::main_group)
+                    .flat_map(|group| group.keys.iter().filter(|key| key.name == "Name"))
+                    .filter_map(|key| key.locale.clone())
+                    .collect::<Vec<String>>();
+
+                locales.sort();
+                locales.dedup();
+                locales.into_iter().map(Candidate::new).collect()
+            },
+            "finder" => vec![Candidate::new("fzf"), Candidate::new("sk")],
+            "mime_type" => {
+                let mut types = entries.iter()
+                    .flat_map(|entry| entry.get_list("MimeType").unwrap_or_default())
+                    .filter(|mime| !mime.is_empty())
+                    .collect::<Vec<String>>();
+
+                types.sort();
+                types.dedup();
+                types.into_iter().map(Candidate::new).collect()
+            },
+            _ => vec![],
+        },
+    };
+
+    let stdout = stdout();
+    let mut out = stdout.lock();
+    position.write(&mut out, shell, &candidates)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// The built-in columns, and the `X-` keys of the `[Desktop Entry]` groups that were scanned
+fn column_candidates(entries: &[DesktopEntry]) -> Vec<Candidate> {
+    let mut keys = entries.iter()
+        .filter_map(DesktopEntry::main_group)
+        .flat_map(|group| group.keys.iter())
+        .filter(|key| key.name.starts_with("X-"))
+        .map(|key| key.name.as_str())
+        .collect::<Vec<&str>>();
+
+    keys.sort_unstable();
+    keys.dedup();
+
+    COLUMNS.iter()
+        .map(|column| Candidate::new(*column))
+        .chain(keys.into_iter().map(|key| Candidate::new(format!("key:{}", key))))
+        .collect()
+}
+
+/// The desktop file ID of every entry, described by its name. Search queries get IDs without
+/// their `.desktop` suffix, which often name the program.
+fn id_candidates(entries: &[DesktopEntry], sources: &[PathBuf], with_suffix: bool) -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = Vec::new();
+    for entry in entries {
+        let id = lsapp::desktop_id(&entry.path, sources);
+        let id = match id.strip_suffix(".desktop") {
+            Some(stem) if !with_suffix => stem.to_string(),
+            _ => id,
+        };
+
+        // Earlier source directories shadow later ones
+        if candidates.iter().any(|candidate| candidate.value == id) {
+            continue;
+        }
+
+        candidates.push(match entry.get("Name") {
+            Some(name) => Candidate::described(id, name),
+            None => Candidate::new(id),
+        });
+    }
+
+    candidates
+}
+
+/// Everything `lsapp show` tells about an entry
+struct Details {
+    entry: DesktopEntry,
+    id: String,
+    source_dir: Option<PathBuf>,
+    /// Files with the same ID in earlier source directories, which hide this one
+    shadowed_by: Vec<PathBuf>,
+    /// Files with the same ID in later source directories, which this one hides
+    shadows: Vec<PathBuf>,
+    exec: Option<std::result::Result<Exec, ExecError>>,
+}
+
+impl Details {
+    /// Read the entry for a desktop file ID or path. Unlike the listing, this doesn't skip
+    /// entries whose TryExec program is missing, since those are often what needs debugging.
+    fn find(id: &str, sources: &[PathBuf]) -> Result<Details> {
+        let path = if id.contains('/') {
+            PathBuf::from(id)
+        } else {
+            let id = if id.ends_with(".desktop") { id.to_string() } else { format!("{}.desktop", id) };
+            lsapp::files_with_id(&id, sources).into_iter()
+                .next()
+                .ok_or_else(|| AppError::NoSuchEntry(id.clone()))?
+        };
+
+        let entry = DesktopEntry::read(&path)?;
+        let id = lsapp::desktop_id(&entry.path, sources);
+        let source_dir = lsapp::source_dir(&entry.path, sources).map(PathBuf::from);
+
+        let others = lsapp::files_with_id(&id, sources);
+        let own_idx = others.iter().position(|other| *other == entry.path);
+        let (shadowed_by, shadows) = match own_idx {
+            Some(idx) => (others[..idx].to_vec(), others[idx + 1..].to_vec()),
+            None => (others, vec![]),
+        };
+
+        let exec = match Exec::from_entry(&entry) {
+            Err(ExecError::Missing) => None,
+            exec => Some(exec),
+        };
+
+        Ok(Details { entry, id, source_dir, shadowed_by, shadows, exec })
+    }
+
+    /// Each group with its keys in file order, and each key with every locale it has
+    fn groups(&self) -> Vec<(&str, Vec<KeyLocales<'_>>)> {
+        self.entry.groups.iter()
+            .map(|group| {
+                let mut names: Vec<&str> = Vec::new();
+                for key in &group.keys {
+                    if !names.contains(&key.name.as_str()) {
+                        names.push(&key.name);
+                    }
+                }
+
+                let keys = names.into_iter()
+                    .map(|name| {
+                        let mut values = group.get_all_locales(name);
+                        // The unlocalized value comes first, wherever it is in the file
+                        values.sort_by_key(|(locale, _)| locale.is_some());
+                        (name, values)
+                    })
+                    .collect();
+
+                (group.name.as_str(), keys)
+            })
+            .collect()
+    }
+
+    fn print(&self) {
+        let line = |label: &str, value: &dyn std::fmt::Display| println!("{:<13}{}", format!("{}:", label), value);
+
+        line("Path", &self.entry.path.display());
+        line("Desktop ID", &self.id);
+        match &self.source_dir {
+            Some(dir) => line("Source dir", &dir.display()),
+            None => line("Source dir", &"(not in a source directory)"),
+        }
+
+        for path in &self.shadowed_by {
+            line("Shadowed by", &path.display());
+        }
+
+        for path in &self.shadows {
+            line("Shadows", &path.display());
+        }
+
+        match &self.exec {
+            Some(Ok(exec)) => {
+                println!("Exec argv:");
+                for (idx, arg) in exec.args().iter().enumerate() {
+                    println!("  [{}] {}", idx, escape_control(arg));
+                }
+            },
+            Some(Err(err)) => line("Exec argv", &format!("invalid, {}", err)),
+            None => (),
+        }
+
+        for (group, keys) in self.groups() {
+            println!();
+            println!("[{}]", group);
+
+            let mut rows = Vec::new();
+            for (name, values) in keys {
+                for (idx, (locale, value)) in values.iter().enumerate() {
+                    let label = match (idx, locale) {
+                        (0, None) => name.to_string(),
+                        (0, Some(locale)) => format!("{}[{}]", name, locale),
+                        (_, Some(locale)) => format!("  [{}]", locale),
+                        (_, None) => String::new(),
+                    };
+
+                    rows.push((label, escape_control(value)));
+                }
+            }
+
+            let width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+            for (label, value) in rows {
+                println!("  {:<width$}  {}", label, value, width = width);
+            }
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let paths = |paths: &[PathBuf]| paths.iter()
+            .map(|path| Value::String(path.display().to_string()))
+            .collect::<Vec<Value>>();
+
+        let groups = self.groups().into_iter()
+            .map(|(group, keys)| {
+                let keys = keys.into_iter()
+                    .map(|(name, values)| {
+                        let value = match values.as_slice() {
+                            [(None, value)] => Value::String(value.clone()),
+                            _ => Field::Localized(values).to_json(),
+                        };
+
+                        (name.to_string(), value)
+                    })
+                    .collect::<Map<String, Value>>();
+
+                (group.to_string(), Value::Object(keys))
+            })
+            .collect::<Map<String, Value>>();
+
+        let mut details = json!({
+            "path": self.entry.path,
+            "desktop_id": self.id,
+            "source_dir": self.source_dir,
+            "shadowed_by": paths(&self.shadowed_by),
+            "shadows": paths(&self.shadows),
+            "exec": null,
+            "groups": groups,
+        });
+
+        match &self.exec {
+            Some(Ok(exec)) => details["exec"] = json!(exec.args()),
+            Some(Err(err)) => details["exec_error"] = json!(err.to_string()),
+            None => (),
+        }
+
+        details
+    }
+}
+
+/// Show line breaks and tabs in a value as escapes, so each value stays on its own line
+fn escape_control(value: &str) -> String {
+    value.replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t")
+        .replace('\r', "\\r")
+}
+
+/// Print a short description of an entry: its name and comment, then the keys worth knowing
+/// about before launching it
+fn print_preview(entry: &DesktopEntry, lang: Option<&str>, sources: &[PathBuf]) {
+    let id = lsapp::desktop_id(&entry.path, sources);
+    let name = entry.get_localized("Name", lang).unwrap_or_else(|| id.clone());
+
+    match entry.get_localized("GenericName", lang).filter(|generic| !generic.is_empty()) {
+        Some(generic) => println!("{} ({})", name, generic),
+        None => println!("{}", name),
+    }
+
+    if let Some(comment) = entry.get_localized("Comment", lang).filter(|comment| !comment.is_empty()) {
+        println!("{}", comment);
+    }
+
+    // Actions are shown by name, falling back to their ID
+    let actions = entry.get_list("Actions").map(|actions| actions.iter()
+        .map(|action| entry.group(&format!("Desktop Action {}", action))
+            .and_then(|group| group.get_localized("Name", lang))
+            .unwrap_or_else(|| action.clone()))
+        .collect::<Vec<String>>());
+
+    let details = [
+        ("ID", Some(id)),
+        ("Exec", entry.get("Exec")),
+        ("Categories", entry.get_list("Categories").map(|categories| categories.join(", "))),
+        ("Keywords", entry.get_localized_list("Keywords", lang).map(|keywords| keywords.join(", "))),
+        ("Actions", actions.map(|actions| actions.join(", "))),
+        ("Terminal", entry.get_bool("Terminal").filter(|terminal| *terminal).map(|_| "yes".to_string())),
+        ("Path", Some(entry.path.display().to_string())),
+    ];
+
+    println!();
+    for (label, value) in details.iter() {
+        if let Some(value) = value.as_ref().filter(|value| !value.is_empty()) {
+            println!("{:<12}{}", format!("{}:", label), value);
+        }
+    }
+}
+
+/// Let the user choose an entry with a fuzzy finder and launch it. The finder shows the listing
+/// without its key, previews the highlighted entry with `lsapp preview`, and prints the chosen
+/// line, whose key is the path of the entry to launch.
+fn pick(entries: &[DesktopEntry], listing: &Listing, finder: &str, lang: Option<&str>, launcher: &Launcher) -> Result<()> {
+    let words = finder.split_whitespace().collect::<Vec<&str>>();
+    let (program, args) = words.split_first().unwrap_or((&"fzf", &[]));
+
+    let exe = env::current_exe()
+        .wrap_err("cannot find the lsapp executable for the preview")?;
+    let mut preview = shell_quote(&exe.to_string_lossy()).into_owned();
+    if let Some(lang) = lang {
+        preview.push_str(&format!(" --lang {}", shell_quote(lang)));
+    }
+
+    let mut child = Command::new(program)
+        .args(args)
+        .arg("--delimiter=\t")
+        .arg("--with-nth=2..")
+        .arg(format!("--preview={} preview {{1}}", preview))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .wrap_err_with(|| format!("failed to run {}", program))?;
+
+    // The finder stops reading once something is chosen, which isn't an error
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = listing.write_scored(&mut stdin, entries, None);
+    }
+
+    let mut selected = String::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        stdout.read_to_string(&mut selected)?;
+    }
+
+    let status = child.wait()?;
+    match status.code() {
+        Some(0) => (),
+        // Nothing matched, or the finder was cancelled
+        Some(1) | Some(130) => return Ok(()),
+        _ => return Err(AppError::FinderFailed(program.to_string(), status).into()),
+    }
+
+    match selected.lines().next().and_then(|line| line.split('\t').next()) {
+        Some(path) if !path.is_empty() => launcher.launch(&DesktopEntry::read(path)?, &[]),
+        _ => Ok(()),
+    }
+}
+
+/// Write the `mimeinfo.cache` of each directory, or with `check`, fail if any of them would change
+fn update_mime_cache(dirs: &[PathBuf], check: bool) -> Result<()> {
+    let mut stale = Vec::new();
+    for dir in dirs {
+        let path = dir.join("mimeinfo.cache");
+        let contents = mimeapps::mime_cache(dir);
+
+        if !check {
+            mimeapps::replace_file(&path, &contents)?;
+        } else if fs::read_to_string(&path).ok().as_deref() != Some(contents.as_str()) {
+            stale.push(path.display().to_string());
+        }
+    }
+
+    if !stale.is_empty() {
+        return Err(AppError::StaleMimeCache(stale.join(", ")).into());
     }
 
     Ok(())
 }
+
+/// Show or change the default application for a type
+fn default(matches: &ArgMatches, entries: &[DesktopEntry], sources: &[PathBuf]) -> Result<()> {
+    let path = lsapp::config_home()
+        .ok_or(AppError::NoConfigDir)?
+        .join("mimeapps.list");
+
+    match matches.subcommand() {
+        Some(("get", get_matches)) => {
+            let mime = get_matches.value_of("mime_type").unwrap_or_default();
+            let id = MimeApps::load(sources, entries).default_for(&MimeDatabase::load().lineage(mime))
+                .ok_or_else(|| AppError::NoHandler(mime.into()))?;
+
+            println!("{}", id);
+        },
+        Some(("set", set_matches)) => {
+            let mime = set_matches.value_of("mime_type").unwrap_or_default();
+            let id = set_matches.value_of("id").unwrap_or_default();
+            if !mimeapps::is_valid_mime_type(mime) {
+                return Err(AppError::InvalidMimeType(mime.into()).into());
+            }
+
+            let id = match find_entry(entries, id, sources) {
+                Ok(entry) => {
+                    let id = lsapp::desktop_id(&entry.path, sources);
+                    // Declaring an alias of the type is as good as declaring the type
+                    let names = MimeDatabase::load().lineage(mime).swap_remove(0);
+                    let declared = entry.get_list("MimeType").unwrap_or_default();
+                    if !set_matches.is_present("force")
+                        && !declared.iter().any(|t| names.iter().any(|name| t.eq_ignore_ascii_case(name))) {
+                        return Err(AppError::UndeclaredMimeType { id, mime: mime.into() }.into());
+                    }
+
+                    id
+                },
+                Err(..) if set_matches.is_present("force") && !id.contains('/') => {
+                    if id.ends_with(".desktop") { id.to_string() } else { format!("{}.desktop", id) }
+                },
+                Err(err) => return Err(err),
+            };
+
+            let mut list = MimeAppsList::load(&path)?;
+            list.set_default(mime, &id);
+            list.save(&path)?;
+        },
+        Some(("unset", unset_matches)) => {
+            let mime = unset_matches.value_of("mime_type").unwrap_or_default();
+            if !mimeapps::is_valid_mime_type(mime) {
+                return Err(AppError::InvalidMimeType(mime.into()).into());
+            }
+
+            let mut list = MimeAppsList::load(&path)?;
+            if list.remove(lsapp::mimeapps::DEFAULT_APPLICATIONS, mime) {
+                list.save(&path)?;
+            }
+        },
+        _ => (),
+    }
+
+    Ok(())
+}
+
+/// The menu items for entries, showing each entry as the listing would. rofi also searches the
+/// generic name and keywords, and hands back the path of the chosen entry.
+fn menu_items(mode: Mode, entries: &[DesktopEntry], listing: &Listing, icons: &IconFinder, lang: Option<&str>) -> Result<Vec<Item>> {
+    let mut out = Vec::new();
+    listing.write_scored(&mut out, entries, None)?;
+    let out = String::from_utf8_lossy(&out);
+
+    let items = entries.iter()
+        .zip(out.split('\0'))
+        .map(|(entry, text)| {
+            let icon = entry.get("Icon").filter(|icon| !icon.is_empty());
+            let icon = if mode.needs_icon_paths() {
+                icon.and_then(|icon| icons.find(&icon)).map(|path| path.display().to_string())
+            } else {
+                icon
+            };
+
+            let meta = entry.get_localized("GenericName", lang).into_iter()
+                .chain(entry.get_localized_list("Keywords", lang).unwrap_or_default())
+                .collect::<Vec<String>>()
+                .join(" ");
+
+            Item {
+                text: text.into(),
+                icon,
+                meta: Some(meta).filter(|meta| !meta.is_empty()),
+                info: Some(entry.path.display().to_string()),
+            }
+        })
+        .collect();
+
+    Ok(items)
+}
+
+fn print_menu(mode: Mode, items: &[Item]) {
+    if let Some(header) = mode.header() {
+        println!("{}", header);
+    }
+
+    for item in items {
+        println!("{}", mode.format(item));
+    }
+}
+
+/// How applications are started
+struct Launcher<'a> {
+    /// Language for the name passed with `%c`
+    lang: Option<&'a str>,
+    /// Terminal command for entries with `Terminal=true`, before `$TERMINAL` and the known ones
+    terminal: Option<&'a str>,
+    /// Print the commands instead of running them
+    dry_run: bool,
+}
+
+impl<'a> Launcher<'a> {
+    /// Start the application of an entry for the given files or URLs, detached from lsapp
+    fn launch(&self, entry: &DesktopEntry, targets: &[Target]) -> Result<()> {
+        let exec = Exec::from_entry(entry)
+            .wrap_err_with(|| format!("cannot launch {}", entry.path.display()))?;
+        let dir = entry.get("Path").filter(|dir| !dir.is_empty());
+
+        let terminal = if entry.get_bool("Terminal").unwrap_or(false) {
+            let terminal = Terminal::detect(self.terminal)
+                .ok_or_else(|| AppError::NoTerminal(entry.path.display().to_string()))?;
+
+            Some(terminal)
+        } else {
+            None
+        };
+
+        for argv in exec.expand(targets, entry, self.lang) {
+            if argv.is_empty() {
+                return Err(ExecError::Empty.into());
+            }
+
+            let argv = match &terminal {
+                Some(terminal) => terminal.wrap(argv),
+                None => argv,
+            };
+
+            if self.dry_run {
+                let command = argv.iter()
+                    .map(|arg| shell_quote(arg))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+
+                match &dir {
+                    Some(dir) => println!("cd {} && {}", shell_quote(dir), command),
+                    None => println!("{}", command),
+                }
+
+                continue;
+            }
+
+            let mut command = Command::new(&argv[0]);
+            command.args(&argv[1..])
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .process_group(0);
+
+            if let Some(dir) = &dir {
+                command.current_dir(dir);
+            }
+
+            command.spawn()
+                .wrap_err_with(|| format!("failed to run {}", argv[0]))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Which entries are shown, and in what order
+struct Order<'a> {
+    filter: Option<EntryFilter<'a>>,
+    /// Columns to sort by, each with whether it's descending
+    sort: Vec<(Column<'a>, bool)>,
+    unique: Vec<Column<'a>>,
+    sources: &'a [PathBuf],
+}
+
+impl<'a> Order<'a> {
+    /// Filter and sort entries, then drop any entry whose `unique` columns match an earlier
+    /// one's. Without sort columns, or between entries that tie, entries keep the order of their
+    /// source directories and then of their paths.
+    fn apply(&self, entries: Vec<DesktopEntry>) -> Vec<DesktopEntry> {
+        let source_idx = |entry: &DesktopEntry| self.sources.iter()
+            .position(|source| entry.path.starts_with(source))
+            .unwrap_or(self.sources.len());
+
+        let mut entries = entries.into_iter()
+            .filter(|entry| self.filter.as_ref().is_none_or(|filter| filter.matches(entry)))
+            .map(|entry| {
+                let keys = self.sort.iter()
+                    .map(|(column, _)| column.field(&entry))
+                    .collect::<Vec<Field>>();
+
+                (keys, entry)
+            })
+            .collect::<Vec<_>>();
+
+        entries.sort_by(|(a_keys, a), (b_keys, b)| {
+            a_keys.iter().zip(b_keys).zip(&self.sort)
+                .map(|((a, b), (_, descending))| compare_fields(a, b, *descending))
+                .find(|ord| *ord != Ordering::Equal)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| source_idx(a).cmp(&source_idx(b)))
+                .then_with(|| a.path.cmp(&b.path))
+        });
+
+        let mut seen = Vec::new();
+        entries.into_iter()
+            .map(|(_, entry)| entry)
+            .filter(|entry| {
+                if self.unique.is_empty() {
+                    return true;
+                }
+
+                let values = self.unique.iter()
+                    .map(|column| column.value(entry))
+                    .collect::<Vec<String>>();
+
+                // Entries with nothing in the unique columns have nothing to be a duplicate of
+                if values.iter().all(String::is_empty) {
+                    return true;
+                }
+
+                if seen.contains(&values) {
+                    return false;
+                }
+
+                seen.push(values);
+                true
+            })
+            .collect()
+    }
+}
+
+/// Compare two values of a sort column. Missing values always sort last, whichever direction
+/// the column is sorted in.
+fn compare_fields(a: &Field, b: &Field, descending: bool) -> Ordering {
+    let ord = match (a, b) {
+        (Field::Empty, Field::Empty) => return Ordering::Equal,
+        (Field::Empty, _) => return Ordering::Greater,
+        (_, Field::Empty) => return Ordering::Less,
+        (Field::Bool(a), Field::Bool(b)) => a.cmp(b),
+        (a, b) => collate(&a.to_text(), &b.to_text()),
+    };
+
+    if descending {
+        ord.reverse()
+    } else {
+        ord
+    }
+}
+
+/// Everything needed to print a listing of entries
+struct Listing<'a> {
+    columns: &'a [Column<'a>],
+    /// Replaces the columns and format when set
+    template: Option<&'a FormatTemplate<'a>>,
+    format: Format,
+    separator: Separator,
+    quote: bool,
+    /// End entries with a NUL instead of a line break
+    null: bool,
+    /// Start each line with the path of the entry, for finders to hand back
+    key: bool,
+    all_locales: bool,
+}
+
+impl<'a> Listing<'a> {
+    fn print(&self, entries: &[DesktopEntry]) -> Result<()> {
+        self.print_scored(entries, None)
+    }
+
+    /// Print entries, preceded by a score column when `scores` has a score for each entry
+    fn print_scored(&self, entries: &[DesktopEntry], scores: Option<&[u32]>) -> Result<()> {
+        let stdout = stdout();
+        self.write_scored(&mut stdout.lock(), entries, scores)
+    }
+
+    fn write_scored<W: Write>(&self, out: &mut W, entries: &[DesktopEntry], scores: Option<&[u32]>) -> Result<()> {
+        let score = |idx: usize| scores.map(|scores| scores[idx]);
+        let key = |entry: &DesktopEntry| if self.key { Some(Column::Path.value(entry)) } else { None };
+
+        if let Some(template) = self.template {
+            let terminator = if self.null { '\0' } else { '\n' };
+            for (idx, entry) in entries.iter().enumerate() {
+                let prefix = key(entry).into_iter()
+                    .chain(score(idx).map(|score| score.to_string()))
+                    .map(|value| value + "\t")
+                    .collect::<String>();
+
+                write!(out, "{}{}{}", prefix, template.render(entry), terminator)?;
+            }
+
+            out.flush()?;
+            return Ok(());
+        }
+
+        match self.format {
+            Format::Text => {
+                let rows = entries.iter()
+                    .enumerate()
+                    .map(|(idx, entry)| score(idx).map(|score| score.to_string()).into_iter()
+                        .chain(self.columns.iter().map(|column| column.value(entry)))
+                        .collect())
+                    .collect::<Vec<Vec<String>>>();
+
+                // The key is read back by other programs, so it's only quoted where CSV needs it
+                if self.key {
+                    let keys = entries.iter().filter_map(key).collect::<Vec<String>>();
+                    output::write_keyed_rows(out, &keys, &rows, self.separator, self.quote, self.null)?;
+                } else {
+                    output::write_rows(out, &rows, self.separator, self.quote, self.null)?;
+                }
+            },
+            Format::Json | Format::JsonLines => {
+                let records = entries.iter()
+                    .enumerate()
+                    .map(|(idx, entry)| {
+                        let mut record = self.record(entry);
+                        if let Some(score) = score(idx) {
+                            record.insert(0, ("score", Field::Number(score.into())));
+                        }
+
+                        record
+                    })
+                    .collect::<Vec<_>>();
+
+                output::write_json(out, &records, self.format == Format::JsonLines)?;
+            },
+        }
+
+        out.flush()?;
+        Ok(())
+    }
+
+    fn record(&self, entry: &DesktopEntry) -> Vec<(&'a str, Field)> {
+        self.columns.iter()
+            .map(|column| {
+                let field = match column.localized_key() {
+                    Some(key) if self.all_locales => Field::Localized(entry.get_all_locales(key)),
+                    _ => column.field(entry),
+                };
+
+                (column.name(), field)
+            })
+            .collect()
+    }
+}
+
+/// Print one JSON object per line for every entry that was added, changed or removed between
+/// two scans
+fn print_events(old: &[DesktopEntry], new: &[DesktopEntry], columns: &[Column]) {
+    let event = |kind: &str, entry: &DesktopEntry| {
+        let data = columns.iter()
+            .map(|column| (column.name().to_string(), column.field(entry).to_json()))
+            .collect::<Map<String, Value>>();
+
+        json!({ "event": kind, "path": entry.path, "data": data })
+    };
+
+    for entry in old {
+        if !new.iter().any(|e| e.path == entry.path) {
+            println!("{}", event("remove", entry));
+        }
+    }
+
+    for entry in new {
+        match old.iter().find(|e| e.path == entry.path) {
+            None => println!("{}", event("add", entry)),
+            Some(prev) if prev != entry => println!("{}", event("update", entry)),
+            Some(..) => (),
+        }
+    }
+}
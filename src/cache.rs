@@ -0,0 +1,442 @@
+//! On-disk cache of parsed desktop entries
+//!
+//! The cache lives at `$XDG_CACHE_HOME/lsapp/entries.cache` and records, for every scanned
+//! directory, the directory's own stamp, its subdirectories, and the stamp and parsed contents of
+//! each file in it. A stamp is the mtime, size and inode of a file; when a directory's stamp is
+//! unchanged its cached listing is reused without calling `read_dir`, and a file is only re-read
+//! when its own stamp changes. Files that failed to parse are recorded too, so they aren't
+//! retried until modified.
+//!
+//! The format is line-based text with tab-separated, escaped fields. The first line names the
+//! format version; a cache written by any other version is discarded rather than misread.
+
+use std::env;
+use std::fs::{self, metadata, File, Metadata, OpenOptions};
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use color_eyre::Result;
+use eyre::WrapErr;
+use shellexpand::tilde;
+
+use crate::entry::{DesktopEntry, Group, Key};
+
+const CACHE_VERSION: u32 = 2;
+const CACHE_MAGIC: &str = "lsapp-cache";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stamp {
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+    pub size: u64,
+    pub inode: u64,
+}
+
+impl From<&Metadata> for Stamp {
+    fn from(meta: &Metadata) -> Stamp {
+        Stamp {
+            mtime: meta.mtime(),
+            mtime_nsec: meta.mtime_nsec(),
+            size: meta.size(),
+            inode: meta.ino(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct DirRecord {
+    path: PathBuf,
+    stamp: Stamp,
+    subdirs: Vec<PathBuf>,
+    files: Vec<FileRecord>,
+}
+
+#[derive(Debug, Clone)]
+struct FileRecord {
+    path: PathBuf,
+    stamp: Stamp,
+    entry: Option<DesktopEntry>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Cache {
+    dirs: Vec<DirRecord>,
+}
+
+impl Cache {
+    /// Location of the cache file, if a cache directory can be determined
+    pub fn default_path() -> Option<PathBuf> {
+        let base = match env::var_os("XDG_CACHE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+        };
+
+        Some(base.join("lsapp").join("entries.cache"))
+    }
+
+    /// Load the cache, starting over with an empty one if it is missing, unreadable, corrupt or
+    /// was written by a different version of lsapp
+    pub fn load<P: AsRef<Path>>(path: P) -> Cache {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| Cache::decode(&contents))
+            .unwrap_or_default()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .wrap_err_with(|| format!("failed to create cache directory {}", parent.display()))?;
+        }
+
+        let (tmp, file) = create_temp(path)
+            .wrap_err_with(|| format!("failed to create a temporary file for {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+
+        let written = self.encode(&mut out)
+            .and_then(|_| out.flush())
+            .wrap_err_with(|| format!("failed to write {}", tmp.display()))
+            .and_then(|_| fs::rename(&tmp, path)
+                .wrap_err_with(|| format!("failed to replace {}", path.display())));
+
+        if written.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+
+        written
+    }
+
+    pub fn clear<P: AsRef<Path>>(path: P) -> Result<()> {
+        let path = path.as_ref();
+        match fs::remove_file(path) {
+            Err(err) if err.kind() != ErrorKind::NotFound => {
+                Err(err).wrap_err_with(|| format!("failed to remove {}", path.display()))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Scan the source directories like `enumerate_desktop_files` and `read_desktop_entries`,
+    /// reusing cached entries for anything that hasn't changed. Entries come back in the same
+    /// order as the uncached scan, and the cache is updated with whatever had to be re-read.
+    pub fn scan<S>(&mut self, sources: S, jobs: usize) -> Result<Vec<DesktopEntry>>
+    where
+        S: IntoIterator,
+        S::Item: AsRef<Path>
+    {
+        let mut scanned = Vec::new();
+        let mut misses = Vec::new();
+        let mut roots = Vec::new();
+
+        for source in sources {
+            let dir = match source.as_ref().to_str() {
+                Some(dir) => PathBuf::from(tilde(dir).into_owned()),
+                None => continue,
+            };
+
+            self.scan_dir(dir.clone(), &mut scanned, &mut misses);
+            roots.push(dir);
+        }
+
+        let paths = misses.iter()
+            .map(|&(dir, file)| scanned[dir].files[file].path.clone())
+            .collect::<Vec<PathBuf>>();
+        let entries = crate::read_desktop_entries(&paths, jobs)?;
+
+        for (&(dir, file), entry) in misses.iter().zip(entries) {
+            scanned[dir].files[file].entry = entry.ok();
+        }
+
+        let res = scanned.iter()
+            .flat_map(|dir| dir.files.iter())
+            .filter_map(|file| file.entry.clone())
+            .collect();
+
+        // Anything left under a scanned root belongs to a directory that no longer exists
+        self.dirs.retain(|dir| !roots.iter().any(|root| dir.path.starts_with(root)));
+        self.dirs.extend(scanned);
+        Ok(res)
+    }
+
+    fn scan_dir(&mut self, dir: PathBuf, scanned: &mut Vec<DirRecord>, misses: &mut Vec<(usize, usize)>) {
+        let stamp = match metadata(&dir) {
+            Ok(meta) => Stamp::from(&meta),
+            Err(..) => return,
+        };
+
+        let old = self.take_dir(&dir);
+        let (listing, subdirs) = match &old {
+            Some(record) if record.stamp == stamp => {
+                let listing = record.files.iter()
+                    .map(|file| file.path.clone())
+                    .collect();
+
+                (listing, record.subdirs.clone())
+            },
+            _ => crate::list_dir(&dir),
+        };
+
+        let mut files = Vec::with_capacity(listing.len());
+        for path in listing {
+            let stamp = match metadata(&path) {
+                Ok(meta) => Stamp::from(&meta),
+                Err(..) => continue,
+            };
+
+            let cached = old.as_ref()
+                .and_then(|record| record.files.iter()
+                    .find(|file| file.path == path && file.stamp == stamp));
+
+            match cached {
+                Some(file) => files.push(file.clone()),
+                None => {
+                    misses.push((scanned.len(), files.len()));
+                    files.push(FileRecord { path, stamp, entry: None });
+                },
+            }
+        }
+
+        scanned.push(DirRecord { path: dir, stamp, subdirs: subdirs.clone(), files });
+        for subdir in subdirs {
+            self.scan_dir(subdir, scanned, misses);
+        }
+    }
+
+    fn take_dir(&mut self, path: &Path) -> Option<DirRecord> {
+        self.dirs.iter()
+            .position(|dir| dir.path == path)
+            .map(|idx| self.dirs.remove(idx))
+    }
+
+    fn encode<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "{} {}", CACHE_MAGIC, CACHE_VERSION)?;
+
+        for dir in &self.dirs {
+            let dir_path = match dir.path.to_str() {
+                Some(path) => path,
+                None => continue,
+            };
+
+            writeln!(out, "dir\t{}\t{}", escape(dir_path), encode_stamp(&dir.stamp))?;
+
+            for subdir in dir.subdirs.iter().filter_map(|subdir| subdir.to_str()) {
+                writeln!(out, "subdir\t{}", escape(subdir))?;
+            }
+
+            for file in &dir.files {
+                let file_path = match file.path.to_str() {
+                    Some(path) => path,
+                    None => continue,
+                };
+
+                let status = if file.entry.is_some() { "ok" } else { "error" };
+                writeln!(out, "file\t{}\t{}\t{}", escape(file_path), encode_stamp(&file.stamp), status)?;
+
+                for group in file.entry.iter().flat_map(|entry| entry.groups.iter()) {
+                    writeln!(out, "group\t{}", escape(&group.name))?;
+
+                    for key in &group.keys {
+                        writeln!(out, "key\t{}\t{}\t{}",
+                            escape(&key.name),
+                            escape(key.locale.as_deref().unwrap_or("")),
+                            escape(&key.value))?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn decode(contents: &str) -> Option<Cache> {
+        let mut lines = contents.lines();
+        if lines.next()? != format!("{} {}", CACHE_MAGIC, CACHE_VERSION) {
+            return None;
+        }
+
+        let mut dirs: Vec<DirRecord> = Vec::new();
+        for line in lines {
+            let fields = line.split('\t').map(unescape).collect::<Vec<String>>();
+
+            match (fields[0].as_str(), fields.len()) {
+                ("dir", 6) => dirs.push(DirRecord {
+                    path: PathBuf::from(&fields[1]),
+                    stamp: decode_stamp(&fields[2..6])?,
+                    subdirs: Vec::new(),
+                    files: Vec::new(),
+                }),
+                ("subdir", 2) => dirs.last_mut()?.subdirs.push(PathBuf::from(&fields[1])),
+                ("file", 7) => {
+                    let entry = match fields[6].as_str() {
+                        "ok" => Some(DesktopEntry { path: PathBuf::from(&fields[1]), groups: Vec::new() }),
+                        "error" => None,
+                        _ => return None,
+                    };
+
+                    dirs.last_mut()?.files.push(FileRecord {
+                        path: PathBuf::from(&fields[1]),
+                        stamp: decode_stamp(&fields[2..6])?,
+                        entry,
+                    });
+                },
+                ("group", 2) => {
+                    let entry = dirs.last_mut()?.files.last_mut()?.entry.as_mut()?;
+                    entry.groups.push(Group { name: fields[1].clone(), keys: Vec::new() });
+                },
+                ("key", 4) => {
+                    let entry = dirs.last_mut()?.files.last_mut()?.entry.as_mut()?;
+                    let locale = if fields[2].is_empty() { None } else { Some(fields[2].clone()) };
+
+                    entry.groups.last_mut()?.keys.push(Key {
+                        name: fields[1].clone(),
+                        locale,
+                        value: fields[3].clone(),
+                    });
+                },
+                _ => return None,
+            }
+        }
+
+        Some(Cache { dirs })
+    }
+}
+
+fn encode_stamp(stamp: &Stamp) -> String {
+    format!("{}\t{}\t{}\t{}", stamp.mtime, stamp.mtime_nsec, stamp.size, stamp.inode)
+}
+
+fn decode_stamp(fields: &[String]) -> Option<Stamp> {
+    Some(Stamp {
+        mtime: fields[0].parse().ok()?,
+        mtime_nsec: fields[1].parse().ok()?,
+        size: fields[2].parse().ok()?,
+        inode: fields[3].parse().ok()?,
+    })
+}
+
+fn escape(field: &str) -> String {
+    let mut res = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => res.push_str("\\\\"),
+            '\t' => res.push_str("\\t"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            c => res.push(c),
+        }
+    }
+
+    res
+}
+
+fn unescape(field: &str) -> String {
+    let mut res = String::with_capacity(field.len());
+    let mut chars = field.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('t') => res.push('\t'),
+            Some('n') => res.push('\n'),
+            Some('r') => res.push('\r'),
+            Some(c) => res.push(c),
+            None => (),
+        }
+    }
+
+    res
+}
+
+/// Create a file next to `path` to write it through before renaming it into place. Each call
+/// gets its own name, so concurrent runs never write into the same file.
+pub(crate) fn create_temp(path: &Path) -> io::Result<(PathBuf, File)> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    loop {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(format!(".{}.{}.tmp", process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let tmp = PathBuf::from(tmp);
+
+        match OpenOptions::new().write(true).create_new(true).open(&tmp) {
+            Ok(file) => return Ok((tmp, file)),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamp(mtime: i64) -> Stamp {
+        Stamp { mtime, mtime_nsec: 0, size: 10, inode: 42 }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let entry = DesktopEntry::parse("/apps/a b.desktop", "[Desktop Entry]\nName=A\tB\nName[de]=Ä\nIcon=\n").unwrap();
+        let cache = Cache {
+            dirs: vec![DirRecord {
+                path: PathBuf::from("/apps"),
+                stamp: stamp(1),
+                subdirs: vec![PathBuf::from("/apps/kde4")],
+                files: vec![
+                    FileRecord { path: entry.path.clone(), stamp: stamp(2), entry: Some(entry) },
+                    FileRecord { path: PathBuf::from("/apps/broken.desktop"), stamp: stamp(3), entry: None },
+                ],
+            }],
+        };
+
+        let mut buf = Vec::new();
+        cache.encode(&mut buf).unwrap();
+        let decoded = Cache::decode(&String::from_utf8(buf).unwrap()).unwrap();
+
+        assert_eq!(decoded.dirs.len(), 1);
+        assert_eq!(decoded.dirs[0].stamp, stamp(1));
+        assert_eq!(decoded.dirs[0].subdirs, vec![PathBuf::from("/apps/kde4")]);
+
+        let files = &decoded.dirs[0].files;
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].stamp, stamp(2));
+        assert!(files[1].entry.is_none());
+
+        let entry = files[0].entry.as_ref().unwrap();
+        assert_eq!(entry.path, PathBuf::from("/apps/a b.desktop"));
+        assert_eq!(entry.get_localized("Name", None).unwrap(), "A\tB");
+        assert_eq!(entry.get_localized("Name", Some("de")).unwrap(), "Ä");
+        assert_eq!(entry.get("Icon").unwrap(), "");
+    }
+
+    #[test]
+    fn test_version_mismatch() {
+        assert!(Cache::decode("lsapp-cache 1\ndir\t/apps\t1\t0\t10\t42\n").is_none());
+        assert!(Cache::decode("lsapp-cache 2\nbogus\n").is_none());
+        assert!(Cache::decode("lsapp-cache 2\n").is_some());
+    }
+
+    #[test]
+    fn test_create_temp() {
+        let dir = env::temp_dir().join(format!("lsapp-cache-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("entries.cache");
+
+        let (first, _) = create_temp(&path).unwrap();
+        let (second, _) = create_temp(&path).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(first.parent(), Some(dir.as_path()));
+
+        Cache::default().save(&path).unwrap();
+        assert!(Cache::decode(&fs::read_to_string(&path).unwrap()).is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,90 @@
+//! Comparing strings the way people expect a sorted list of applications to look
+//!
+//! Strings are compared without regard to case or accents, so `Éditeur` sorts next to `editor`
+//! rather than after `Zoom`, and runs of digits compare by their numeric value, so `App 2` comes
+//! before `App 10`. Strings that are equal by those rules fall back to comparing code points, which
+//! keeps the order total.
+//!
+//! The rules don't depend on the locale. `--lang` picks which translation of a name is sorted,
+//! but there is no tailoring like Swedish sorting `ä` after `z`; it sorts with `a` everywhere.
+
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+pub fn collate(a: &str, b: &str) -> Ordering {
+    compare_natural(&fold(a), &fold(b))
+        .then_with(|| a.cmp(b))
+}
+
+/// Lowercase a string and strip its accents
+pub(crate) fn fold(s: &str) -> String {
+    s.nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn compare_natural(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(..)) => return Ordering::Less,
+            (Some(..), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let ord = compare_numbers(&take_digits(&mut a), &take_digits(&mut b));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            },
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+
+                a.next();
+                b.next();
+            },
+        }
+    }
+}
+
+fn take_digits(chars: &mut Peekable<Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.peek().copied().filter(char::is_ascii_digit) {
+        digits.push(c);
+        chars.next();
+    }
+
+    digits
+}
+
+/// Compare two runs of digits by value, without limiting how long they can be
+fn compare_numbers(a: &str, b: &str) -> Ordering {
+    let a = a.trim_start_matches('0');
+    let b = b.trim_start_matches('0');
+
+    a.len().cmp(&b.len())
+        .then_with(|| a.cmp(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collate() {
+        let mut names = vec!["Zoom", "editor", "App 10", "Éditeur", "app 2", "App 2", "Écran"];
+        names.sort_by(|a, b| collate(a, b));
+
+        assert_eq!(names, vec!["App 2", "app 2", "App 10", "Écran", "Éditeur", "editor", "Zoom"]);
+        assert_eq!(collate("v007", "v7"), Ordering::Less);
+        assert_eq!(collate("same", "same"), Ordering::Equal);
+    }
+}
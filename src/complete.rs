@@ -0,0 +1,373 @@
+//! Completing lsapp's command line in bash, zsh and fish
+//!
+//! The shell scripts only hand the words typed so far to the hidden `lsapp complete` subcommand.
+//! Flags and subcommands are found by walking the clap app, so they can't drift from what lsapp
+//! accepts, while the values of arguments are listed by the caller, which can look at the scanned
+//! entries. When nothing is printed, the scripts fall back to completing file names.
+
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use clap::{App, AppSettings, Arg, ArgSettings};
+use thiserror::Error;
+
+/// Names of the supported shells
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+const BASH_SCRIPT: &str = r#"_lsapp() {
+    local line=${COMP_LINE:0:$COMP_POINT}
+    local -a words
+    read -ra words <<< "$line"
+    [[ $line == *[[:space:]] ]] && words+=("")
+
+    local IFS=$'\n'
+    COMPREPLY=($(lsapp complete --shell bash -- "${words[@]:1}" 2>/dev/null))
+}
+
+complete -o default -F _lsapp lsapp
+"#;
+
+const ZSH_SCRIPT: &str = r#"#compdef lsapp
+
+_lsapp() {
+    local -a candidates
+    candidates=("${(@f)$(lsapp complete --shell zsh -- "${(@)words[2,CURRENT]}" 2>/dev/null)}")
+
+    if (( ${#candidates} > 1 || ${#candidates[1]} )); then
+        _describe -t lsapp lsapp candidates
+    else
+        _files
+    fi
+}
+
+if [[ $zsh_eval_context[-1] == loadautofunc ]]; then
+    _lsapp "$@"
+else
+    compdef _lsapp lsapp
+fi
+"#;
+
+const FISH_SCRIPT: &str = r#"function __lsapp_complete
+    set -l tokens (commandline -opc)
+    set -l current (commandline -ct)
+    set -l candidates (lsapp complete --shell fish -- $tokens[2..-1] "$current" 2>/dev/null)
+
+    if test (count $candidates) -gt 0
+        printf '%s\n' $candidates
+    else
+        __fish_complete_path "$current"
+    end
+end
+
+complete -c lsapp -f -a '(__lsapp_complete)'
+"#;
+
+#[derive(Error, Debug)]
+#[error("unsupported shell `{0}`; use one of {}", SHELLS.join(", "))]
+pub struct UnknownShell(String);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl FromStr for Shell {
+    type Err = UnknownShell;
+
+    fn from_str(s: &str) -> Result<Shell, UnknownShell> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(UnknownShell(s.into())),
+        }
+    }
+}
+
+impl Shell {
+    /// The script that hooks lsapp's completion into the shell
+    pub fn script(self) -> &'static str {
+        match self {
+            Shell::Bash => BASH_SCRIPT,
+            Shell::Zsh => ZSH_SCRIPT,
+            Shell::Fish => FISH_SCRIPT,
+        }
+    }
+}
+
+/// A possible completion, with a description for the shells that show one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub value: String,
+    pub description: Option<String>,
+}
+
+impl Candidate {
+    pub fn new(value: impl Into<String>) -> Candidate {
+        Candidate { value: value.into(), description: None }
+    }
+
+    pub fn described(value: impl Into<String>, description: impl Into<String>) -> Candidate {
+        Candidate { value: value.into(), description: Some(description.into()) }
+    }
+}
+
+/// What the word being completed stands for
+#[derive(Debug)]
+pub enum Context<'a, 'help> {
+    /// A flag or subcommand, whose candidates are known from the app
+    Words(Vec<Candidate>),
+    /// The value of an argument, which only the caller can list
+    Value(&'a Arg<'help>),
+}
+
+/// Where the cursor is on the command line
+#[derive(Debug)]
+pub struct Position<'a, 'help> {
+    /// The part of the current word that's already complete, like `--data=name,`
+    pub prefix: String,
+    /// The rest of the current word, which candidates have to start with
+    pub current: String,
+    pub context: Context<'a, 'help>,
+}
+
+impl<'a, 'help> Position<'a, 'help> {
+    /// Work out what the last of `words` is, given the words before it. `words` doesn't include
+    /// the name of the program, and its last word is empty when completing a new word.
+    pub fn locate(app: &'a App<'help>, words: &[String]) -> Position<'a, 'help> {
+        let (current, done) = match words.split_last() {
+            Some((current, done)) => (current.as_str(), done),
+            None => ("", &[][..]),
+        };
+
+        let mut app = app;
+        let mut positionals = 0;
+        let mut pending: Option<&'a Arg<'help>> = None;
+        let mut only_positionals = false;
+
+        for word in done {
+            if pending.take().is_some() {
+                continue;
+            }
+
+            if !only_positionals && word == "--" {
+                only_positionals = true;
+            } else if !only_positionals && word.starts_with("--") {
+                let (name, value) = split_long(word);
+                pending = find_long(app, name).filter(|arg| takes_value(arg) && value.is_none());
+            } else if !only_positionals && word.starts_with('-') && word.len() > 1 {
+                pending = pending_short(app, &word[1..]);
+            } else if let Some(subcommand) = find_subcommand(app, word).filter(|_| positionals == 0) {
+                app = subcommand;
+            } else {
+                positionals += 1;
+            }
+        }
+
+        if let Some(arg) = pending {
+            return Position::value(arg, "", current);
+        }
+
+        if !only_positionals && current.starts_with("--") {
+            let (name, value) = split_long(current);
+            return match (find_long(app, name), value) {
+                (Some(arg), Some(value)) if takes_value(arg) => Position::value(arg, &current[..name.len() + 3], value),
+                _ => Position::words(current, long_flags(app)),
+            };
+        }
+
+        if !only_positionals && current.starts_with('-') {
+            let short = current[1..].chars().next().and_then(|c| find_short(app, c));
+            return match short {
+                Some(arg) if takes_value(arg) && current.len() > 2 => Position::value(arg, &current[..2], &current[2..]),
+                _ => Position::words(current, long_flags(app)),
+            };
+        }
+
+        match find_positional(app, positionals) {
+            Some(arg) => Position::value(arg, "", current),
+            None if positionals == 0 => Position::words(current, subcommands(app)),
+            None => Position::words(current, vec![]),
+        }
+    }
+
+    fn words(current: &str, candidates: Vec<Candidate>) -> Position<'a, 'help> {
+        Position { prefix: String::new(), current: current.into(), context: Context::Words(candidates) }
+    }
+
+    /// The value of an argument, where each part of a comma-separated list is completed on its
+    /// own. Arguments with a fixed set of values are completed right away.
+    fn value(arg: &'a Arg<'help>, prefix: &str, value: &str) -> Position<'a, 'help> {
+        let split = if arg.is_set(ArgSettings::UseValueDelimiter) { value.rfind(',') } else { None };
+        let (done, current) = match split {
+            Some(idx) => value.split_at(idx + 1),
+            None => ("", value),
+        };
+
+        let context = match arg.get_possible_values() {
+            Some(values) => Context::Words(values.iter().copied().map(Candidate::new).collect()),
+            None => Context::Value(arg),
+        };
+
+        Position { prefix: format!("{}{}", prefix, done), current: current.into(), context }
+    }
+
+    /// Print the candidates that match the current word, one per line, in the shell's format.
+    /// Each is printed as the whole word, with the prefix, except in bash, which only replaces
+    /// what follows the last `=` or `:` in a word.
+    pub fn write<W: Write>(&self, out: &mut W, shell: Shell, candidates: &[Candidate]) -> io::Result<()> {
+        let word = format!("{}{}", self.prefix, self.current);
+        let skip = match shell {
+            Shell::Bash => word.rfind(&['=', ':'][..]).map_or(0, |idx| idx + 1),
+            _ => 0,
+        };
+
+        for candidate in candidates.iter().filter(|candidate| candidate.value.starts_with(&self.current)) {
+            let value = format!("{}{}", self.prefix, candidate.value);
+            let value = &value[skip..];
+            let description = candidate.description.as_deref()
+                .and_then(|description| description.lines().next())
+                .filter(|description| !description.is_empty());
+
+            match (shell, description) {
+                (Shell::Zsh, Some(description)) => writeln!(out, "{}:{}", value.replace(':', "\\:"), description)?,
+                (Shell::Zsh, None) => writeln!(out, "{}", value.replace(':', "\\:"))?,
+                (Shell::Fish, Some(description)) => writeln!(out, "{}\t{}", value, description)?,
+                _ => writeln!(out, "{}", value)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn takes_value(arg: &Arg) -> bool {
+    arg.is_set(ArgSettings::TakesValue)
+}
+
+fn is_positional(arg: &Arg) -> bool {
+    arg.get_long().is_none() && arg.get_short().is_none()
+}
+
+/// Split `--name=value` into its name and value
+fn split_long(word: &str) -> (&str, Option<&str>) {
+    match word[2..].find('=') {
+        Some(idx) => (&word[2..idx + 2], Some(&word[idx + 3..])),
+        None => (&word[2..], None),
+    }
+}
+
+fn find_long<'a, 'help>(app: &'a App<'help>, name: &str) -> Option<&'a Arg<'help>> {
+    app.get_arguments().find(|arg| arg.get_long() == Some(name))
+}
+
+fn find_short<'a, 'help>(app: &'a App<'help>, c: char) -> Option<&'a Arg<'help>> {
+    app.get_arguments().find(|arg| arg.get_short() == Some(c))
+}
+
+/// The option waiting for its value after a cluster of short flags like `-qd`, if the value
+/// isn't attached as in `-dname`
+fn pending_short<'a, 'help>(app: &'a App<'help>, flags: &str) -> Option<&'a Arg<'help>> {
+    for (idx, c) in flags.char_indices() {
+        match find_short(app, c) {
+            Some(arg) if takes_value(arg) => {
+                return (idx + c.len_utf8() == flags.len()).then_some(arg);
+            },
+            _ => (),
+        }
+    }
+
+    None
+}
+
+fn find_subcommand<'a, 'help>(app: &'a App<'help>, name: &str) -> Option<&'a App<'help>> {
+    app.get_subcommands().find(|subcommand| subcommand.get_name() == name)
+}
+
+/// The positional argument at an index, where a last argument taking several values takes
+/// every index after it
+fn find_positional<'a, 'help>(app: &'a App<'help>, idx: usize) -> Option<&'a Arg<'help>> {
+    let positionals = app.get_arguments()
+        .filter(|arg| is_positional(arg) && !arg.is_set(ArgSettings::Hidden))
+        .collect::<Vec<_>>();
+
+    positionals.get(idx).copied().or_else(|| positionals.last()
+        .copied()
+        .filter(|arg| arg.is_set(ArgSettings::MultipleValues)))
+}
+
+fn long_flags(app: &App) -> Vec<Candidate> {
+    app.get_arguments()
+        .filter(|arg| !arg.is_set(ArgSettings::Hidden))
+        .filter_map(|arg| arg.get_long().map(|long| {
+            let flag = format!("--{}", long);
+            match arg.get_about() {
+                Some(about) => Candidate::described(flag, about),
+                None => Candidate::new(flag),
+            }
+        }))
+        .chain(std::iter::once(Candidate::described("--help", "Prints help information")))
+        .collect()
+}
+
+fn subcommands(app: &App) -> Vec<Candidate> {
+    app.get_subcommands()
+        .filter(|subcommand| !subcommand.is_set(AppSettings::Hidden))
+        .map(|subcommand| match subcommand.get_about() {
+            Some(about) => Candidate::described(subcommand.get_name(), about),
+            None => Candidate::new(subcommand.get_name()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::clap_app;
+
+    fn complete(words: &[&str], shell: Shell, values: &[&str]) -> String {
+        let app = clap_app!(test =>
+            (@arg data: -d --data +takes_value +multiple_occurrences +multiple_values +use_delimiter +require_delimiter "Columns")
+            (@arg quote: -q --quote "Quote values")
+            (@arg format: -f --format +takes_value possible_values(&["text", "json"]) "Format")
+            (@subcommand run =>
+                (about: "Run an application")
+                (@arg id: +required "Desktop file ID")
+                (@arg targets: +multiple_values "Files"))
+            (@subcommand hidden =>
+                (@setting Hidden)));
+
+        let words = words.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+        let position = Position::locate(&app, &words);
+        let candidates = match position.context {
+            Context::Words(ref candidates) => candidates.clone(),
+            Context::Value(arg) => values.iter().map(|v| Candidate::new(format!("{}:{}", arg.get_name(), v))).collect(),
+        };
+
+        let mut out = Vec::new();
+        position.write(&mut out, shell, &candidates).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_flags_and_subcommands() {
+        assert_eq!(complete(&[""], Shell::Bash, &[]), "run\n");
+        assert_eq!(complete(&["--q"], Shell::Fish, &[]), "--quote\tQuote values\n");
+        assert_eq!(complete(&["-q", "--format", "j"], Shell::Bash, &[]), "json\n");
+        assert_eq!(complete(&["--format=t"], Shell::Zsh, &[]), "--format=text\n");
+        assert_eq!(complete(&["--format=t"], Shell::Bash, &[]), "text\n");
+    }
+
+    #[test]
+    fn test_values() {
+        assert_eq!(complete(&["-d", "data:a,data:b"], Shell::Fish, &["a", "bb"]), "data:a,data:bb\n");
+        assert_eq!(complete(&["-qd", ""], Shell::Bash, &["x"]), "data:x\n");
+        assert_eq!(complete(&["-ddata:"], Shell::Zsh, &["x"]), "-ddata\\:x\n");
+        assert_eq!(complete(&["run", ""], Shell::Bash, &["x"]), "id:x\n");
+        assert_eq!(complete(&["run", "-q", "app", "--", "-"], Shell::Fish, &["x"]), "");
+        assert_eq!(complete(&["run", "app", "f", "z"], Shell::Fish, &["x"]), "");
+        assert_eq!(complete(&["run", "app", "targets:"], Shell::Fish, &["x"]), "targets:x\n");
+    }
+}
@@ -0,0 +1,236 @@
+//! The config file, `$XDG_CONFIG_HOME/lsapp/config.toml`
+//!
+//! Settings at the top level of the file apply to every run, and each table under `[profiles]`
+//! is a set of settings selected with `--profile NAME`, which override the top level ones:
+//!
+//! ```toml
+//! extra-sources = ["~/.local/share/flatpak/exports/share/applications"]
+//! columns = ["name", "comment", "path"]
+//! lang = "de"
+//!
+//! [profiles.games]
+//! filter = "categories has Game"
+//! sort = ["name"]
+//! separator = "spaces"
+//! ```
+//!
+//! `sources` replaces the default source directories, while `extra-sources` is added after them;
+//! a profile's `extra-sources` come after the top level ones. Lists can also be written as a
+//! comma-separated string, as on the command line. The command line and the environment take
+//! precedence over anything set here.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+use toml::value::Table;
+use toml::Value;
+
+use crate::output::Separator;
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("cannot read {0}: {1}")]
+    Read(PathBuf, io::Error),
+
+    #[error("invalid config: {0}")]
+    Syntax(toml::de::Error),
+
+    #[error("invalid config: `{key}` must be {expected}")]
+    InvalidValue { key: String, expected: &'static str },
+
+    #[error("invalid config: unknown setting `{0}`")]
+    UnknownSetting(String),
+
+    #[error("no profile named `{0}` in the config")]
+    UnknownProfile(String),
+}
+
+/// Settings from the config file, where `None` leaves a setting to its default
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Settings {
+    pub sources: Option<Vec<String>>,
+    pub extra_sources: Vec<String>,
+    pub columns: Option<Vec<String>>,
+    pub separator: Option<Separator>,
+    pub quote: Option<bool>,
+    pub lang: Option<String>,
+    pub filter: Option<String>,
+    pub sort: Option<Vec<String>>,
+    pub unique: Option<Vec<String>>,
+    pub terminal: Option<String>,
+    pub finder: Option<String>,
+}
+
+impl Settings {
+    fn from_table(table: &Table, prefix: &str) -> Result<Settings, ConfigError> {
+        let mut settings = Settings::default();
+
+        for (key, value) in table {
+            let name = format!("{}{}", prefix, key);
+            match key.as_str() {
+                "sources" => settings.sources = Some(list(&name, value)?),
+                "extra-sources" => settings.extra_sources = list(&name, value)?,
+                "columns" => settings.columns = Some(list(&name, value)?),
+                "separator" => settings.separator = Some(match value.as_str() {
+                    Some("comma") => Separator::Comma,
+                    Some("tab") => Separator::Tab,
+                    Some("spaces") => Separator::Spaces,
+                    _ => return Err(invalid(&name, "one of \"comma\", \"tab\" or \"spaces\"")),
+                }),
+                "quote" => settings.quote = Some(value.as_bool().ok_or_else(|| invalid(&name, "true or false"))?),
+                "lang" => settings.lang = Some(string(&name, value)?),
+                "filter" => settings.filter = Some(string(&name, value)?),
+                "sort" => settings.sort = Some(list(&name, value)?),
+                "unique" => settings.unique = Some(list(&name, value)?),
+                "terminal" => settings.terminal = Some(string(&name, value)?),
+                "finder" => settings.finder = Some(string(&name, value)?),
+                _ => return Err(ConfigError::UnknownSetting(name)),
+            }
+        }
+
+        Ok(settings)
+    }
+
+    /// Apply a profile on top of these settings
+    fn merge(mut self, profile: &Settings) -> Settings {
+        let profile = profile.clone();
+
+        self.extra_sources.extend(profile.extra_sources);
+        Settings {
+            sources: profile.sources.or(self.sources),
+            extra_sources: self.extra_sources,
+            columns: profile.columns.or(self.columns),
+            separator: profile.separator.or(self.separator),
+            quote: profile.quote.or(self.quote),
+            lang: profile.lang.or(self.lang),
+            filter: profile.filter.or(self.filter),
+            sort: profile.sort.or(self.sort),
+            unique: profile.unique.or(self.unique),
+            terminal: profile.terminal.or(self.terminal),
+            finder: profile.finder.or(self.finder),
+        }
+    }
+}
+
+fn invalid(key: &str, expected: &'static str) -> ConfigError {
+    ConfigError::InvalidValue { key: key.into(), expected }
+}
+
+fn string(key: &str, value: &Value) -> Result<String, ConfigError> {
+    value.as_str()
+        .map(String::from)
+        .ok_or_else(|| invalid(key, "a string"))
+}
+
+/// A list given as an array of strings, or as a comma-separated string
+fn list(key: &str, value: &Value) -> Result<Vec<String>, ConfigError> {
+    match value {
+        Value::String(s) => Ok(s.split(',').map(String::from).collect()),
+        Value::Array(items) => items.iter()
+            .map(|item| item.as_str().map(String::from))
+            .collect::<Option<Vec<String>>>()
+            .ok_or_else(|| invalid(key, "a list of strings")),
+        _ => Err(invalid(key, "a list of strings")),
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    pub settings: Settings,
+    pub profiles: BTreeMap<String, Settings>,
+}
+
+impl Config {
+    /// Location of the config file, if a config directory can be determined
+    pub fn default_path() -> Option<PathBuf> {
+        crate::config_home().map(|dir| dir.join("lsapp").join("config.toml"))
+    }
+
+    /// Load the config file, where a missing file is an empty config
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+        match fs::read_to_string(path.as_ref()) {
+            Ok(contents) => Config::parse(&contents),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(ConfigError::Read(path.as_ref().into(), err)),
+        }
+    }
+
+    pub fn parse(contents: &str) -> Result<Config, ConfigError> {
+        let mut table = toml::from_str::<Table>(contents)
+            .map_err(ConfigError::Syntax)?;
+
+        let mut profiles = BTreeMap::new();
+        if let Some(value) = table.remove("profiles") {
+            let value = match value {
+                Value::Table(value) => value,
+                _ => return Err(invalid("profiles", "a table of profiles")),
+            };
+
+            for (name, profile) in value {
+                let prefix = format!("profiles.{}.", name);
+                let profile = match profile {
+                    Value::Table(profile) => Settings::from_table(&profile, &prefix)?,
+                    _ => return Err(invalid(&format!("profiles.{}", name), "a table of settings")),
+                };
+
+                profiles.insert(name, profile);
+            }
+        }
+
+        Ok(Config { settings: Settings::from_table(&table, "")?, profiles })
+    }
+
+    /// The settings in effect with a profile, or with none
+    pub fn settings(&self, profile: Option<&str>) -> Result<Settings, ConfigError> {
+        match profile {
+            Some(name) => self.profiles.get(name)
+                .map(|profile| self.settings.clone().merge(profile))
+                .ok_or_else(|| ConfigError::UnknownProfile(name.into())),
+            None => Ok(self.settings.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profiles() {
+        let config = Config::parse(r#"
+            extra-sources = ["~/flatpak"]
+            columns = "name,exec"
+            quote = true
+
+            [profiles.games]
+            extra-sources = ["~/games"]
+            columns = ["name"]
+            separator = "spaces"
+        "#).unwrap();
+
+        let base = config.settings(None).unwrap();
+        assert_eq!(base.columns, Some(vec!["name".into(), "exec".into()]));
+        assert_eq!(base.separator, None);
+
+        let games = config.settings(Some("games")).unwrap();
+        assert_eq!(games.extra_sources, vec!["~/flatpak", "~/games"]);
+        assert_eq!(games.columns, Some(vec!["name".into()]));
+        assert_eq!(games.separator, Some(Separator::Spaces));
+        assert_eq!(games.quote, Some(true));
+
+        assert!(matches!(config.settings(Some("work")), Err(ConfigError::UnknownProfile(..))));
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = |contents: &str| Config::parse(contents).unwrap_err().to_string();
+
+        assert_eq!(err("colums = []"), "invalid config: unknown setting `colums`");
+        assert_eq!(err("[profiles.x]\nquote = \"yes\""), "invalid config: `profiles.x.quote` must be true or false");
+        assert_eq!(err("sort = [1]"), "invalid config: `sort` must be a list of strings");
+        assert!(err("columns = ").starts_with("invalid config: "));
+    }
+}
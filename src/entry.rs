@@ -0,0 +1,288 @@
+//! Desktop entries assembled from parsed .desktop files
+//!
+//! Values are kept as they appear in the file; escape sequences are only resolved when a value
+//! is read through one of the typed accessors.
+
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use color_eyre::Result;
+use eyre::WrapErr;
+
+use crate::parser::Parser;
+use crate::tryexec::TryExec;
+
+/// Heading of the group holding the entry's main keys
+pub const DESKTOP_ENTRY: &str = "Desktop Entry";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DesktopEntry {
+    pub path: PathBuf,
+    pub groups: Vec<Group>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    pub name: String,
+    pub keys: Vec<Key>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Key {
+    pub name: String,
+    pub locale: Option<String>,
+    pub value: String,
+}
+
+impl DesktopEntry {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<DesktopEntry> {
+        let path = path.as_ref();
+        let contents = read_to_string(path)
+            .wrap_err_with(|| format!("failed to read {}", path.display()))?;
+
+        DesktopEntry::parse(path, contents)
+            .wrap_err_with(|| format!("failed to parse {}", path.display()))
+    }
+
+    pub fn parse(path: impl Into<PathBuf>, contents: impl Into<String>) -> Result<DesktopEntry> {
+        let groups = Parser::new(contents).parse()?
+            .into_iter()
+            .map(|section| Group {
+                name: section.heading,
+                keys: section.entries.into_iter()
+                    .map(|entry| Key {
+                        name: entry.key,
+                        locale: entry.lang,
+                        value: entry.value.raw(),
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Ok(DesktopEntry { path: path.into(), groups })
+    }
+
+    pub fn group(&self, name: &str) -> Option<&Group> {
+        self.groups.iter().find(|group| group.name == name)
+    }
+
+    /// The `[Desktop Entry]` group, which every valid entry starts with
+    pub fn main_group(&self) -> Option<&Group> {
+        self.group(DESKTOP_ENTRY)
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.main_group().and_then(|group| group.get(key))
+    }
+
+    pub fn get_localized(&self, key: &str, lang: Option<&str>) -> Option<String> {
+        self.main_group().and_then(|group| group.get_localized(key, lang))
+    }
+
+    pub fn get_list(&self, key: &str) -> Option<Vec<String>> {
+        self.main_group().and_then(|group| group.get_list(key))
+    }
+
+    pub fn get_localized_list(&self, key: &str, lang: Option<&str>) -> Option<Vec<String>> {
+        self.main_group().and_then(|group| group.get_localized_list(key, lang))
+    }
+
+    pub fn get_all_locales(&self, key: &str) -> Vec<(Option<String>, String)> {
+        self.main_group().map_or(vec![], |group| group.get_all_locales(key))
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        self.main_group().and_then(|group| group.get_bool(key))
+    }
+
+    /// Check whether the program named by `TryExec` is installed
+    pub fn try_exec(&self) -> TryExec {
+        match self.get("TryExec") {
+            Some(program) if !program.is_empty() => TryExec::resolve(&program),
+            _ => TryExec::Unset,
+        }
+    }
+}
+
+impl Group {
+    /// Look up the raw value of a key for exactly the given locale
+    pub fn get_raw(&self, key: &str, locale: Option<&str>) -> Option<&str> {
+        self.keys.iter()
+            .rev()
+            .find(|k| k.name == key && k.locale.as_deref() == locale)
+            .map(|k| k.value.as_str())
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.get_raw(key, None).map(unescape)
+    }
+
+    /// Look up a key using the locale matching rules from the desktop entry spec, falling back
+    /// to the unlocalized value
+    pub fn get_localized(&self, key: &str, lang: Option<&str>) -> Option<String> {
+        self.get_localized_raw(key, lang).map(unescape)
+    }
+
+    fn get_localized_raw(&self, key: &str, lang: Option<&str>) -> Option<&str> {
+        lang.map_or(vec![], locale_variants)
+            .iter()
+            .find_map(|locale| self.get_raw(key, Some(locale)))
+            .or_else(|| self.get_raw(key, None))
+    }
+
+    pub fn get_list(&self, key: &str) -> Option<Vec<String>> {
+        self.get_raw(key, None).map(split_list)
+    }
+
+    /// Look up a list of localized strings, like `Keywords`, with the same locale matching as
+    /// `get_localized`
+    pub fn get_localized_list(&self, key: &str, lang: Option<&str>) -> Option<Vec<String>> {
+        self.get_localized_raw(key, lang).map(split_list)
+    }
+
+    /// Every locale a key is defined for, in file order, with `None` for the unlocalized value.
+    /// A locale that appears more than once keeps its last value, as with `get_raw`.
+    pub fn get_all_locales(&self, key: &str) -> Vec<(Option<String>, String)> {
+        let mut values: Vec<(Option<String>, String)> = Vec::new();
+        for k in self.keys.iter().filter(|k| k.name == key) {
+            match values.iter_mut().find(|(locale, _)| *locale == k.locale) {
+                Some(existing) => existing.1 = unescape(&k.value),
+                None => values.push((k.locale.clone(), unescape(&k.value))),
+            }
+        }
+
+        values
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get_raw(key, None) {
+            Some("true") => Some(true),
+            Some("false") => Some(false),
+            _ => None,
+        }
+    }
+}
+
+/// Expand a locale of the form `lang_COUNTRY.ENCODING@MODIFIER` into the keys to try, most
+/// specific first. The encoding is never used for matching.
+pub fn locale_variants(locale: &str) -> Vec<String> {
+    let (locale, modifier) = match locale.find('@') {
+        Some(idx) => (&locale[..idx], Some(&locale[idx + 1..])),
+        None => (locale, None),
+    };
+
+    let locale = match locale.find('.') {
+        Some(idx) => &locale[..idx],
+        None => locale,
+    };
+
+    let (lang, country) = match locale.find('_') {
+        Some(idx) => (&locale[..idx], Some(&locale[idx + 1..])),
+        None => (locale, None),
+    };
+
+    let mut variants = Vec::with_capacity(4);
+    if let (Some(country), Some(modifier)) = (country, modifier) {
+        variants.push(format!("{}_{}@{}", lang, country, modifier));
+    }
+
+    if let Some(country) = country {
+        variants.push(format!("{}_{}", lang, country));
+    }
+
+    if let Some(modifier) = modifier {
+        variants.push(format!("{}@{}", lang, modifier));
+    }
+
+    variants.push(lang.to_string());
+    variants
+}
+
+/// Resolve the `\s`, `\n`, `\t`, `\r` and `\\` escapes of a string value
+pub fn unescape(value: &str) -> String {
+    let mut res = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('s') => res.push(' '),
+            Some('n') => res.push('\n'),
+            Some('t') => res.push('\t'),
+            Some('r') => res.push('\r'),
+            Some('\\') => res.push('\\'),
+            Some(c) => {
+                res.push('\\');
+                res.push(c);
+            },
+            None => res.push('\\'),
+        }
+    }
+
+    res
+}
+
+/// Split a `;`-separated list value, honouring `\;` escapes and ignoring the trailing separator
+pub fn split_list(value: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut item = String::new();
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => items.push(unescape(&std::mem::take(&mut item))),
+            '\\' => match chars.next() {
+                Some(';') => item.push(';'),
+                Some(c) => {
+                    item.push('\\');
+                    item.push(c);
+                },
+                None => item.push('\\'),
+            },
+            c => item.push(c),
+        }
+    }
+
+    if !item.is_empty() {
+        items.push(unescape(&item));
+    }
+
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locale_variants() {
+        assert_eq!(locale_variants("sr_YU.UTF-8@Latn"), vec!["sr_YU@Latn", "sr_YU", "sr@Latn", "sr"]);
+        assert_eq!(locale_variants("de_DE"), vec!["de_DE", "de"]);
+        assert_eq!(locale_variants("fr"), vec!["fr"]);
+    }
+
+    #[test]
+    fn test_get_localized() {
+        let entry = DesktopEntry::parse("test.desktop", "[Desktop Entry]\nName=Files\nName[de]=Dateien\nName[sr@Latn]=Datoteke\n").unwrap();
+
+        assert_eq!(entry.get_localized("Name", None).unwrap(), "Files");
+        assert_eq!(entry.get_localized("Name", Some("de_AT.UTF-8")).unwrap(), "Dateien");
+        assert_eq!(entry.get_localized("Name", Some("sr_YU@Latn")).unwrap(), "Datoteke");
+        assert_eq!(entry.get_localized("Name", Some("fr")).unwrap(), "Files");
+
+        let entry = DesktopEntry::parse("test.desktop", "[Desktop Entry]\nKeywords=files;folders;\nKeywords[de]=Dateien;Ordner;\n").unwrap();
+        assert_eq!(entry.get_localized_list("Keywords", Some("de_DE")).unwrap(), vec!["Dateien", "Ordner"]);
+        assert_eq!(entry.get_localized_list("Keywords", None).unwrap(), vec!["files", "folders"]);
+    }
+
+    #[test]
+    fn test_values() {
+        assert_eq!(unescape(r"a\sb\\c\td"), "a b\\c\td");
+        assert_eq!(split_list(r"GTK;Utility\;Extra;"), vec!["GTK", "Utility;Extra"]);
+        assert_eq!(split_list(""), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,338 @@
+//! Turning an `Exec` key into the command lines that launch an application
+//!
+//! An `Exec` value is split into arguments with the quoting rules of the desktop entry spec:
+//! arguments are separated by spaces, and an argument in double quotes can contain spaces and the
+//! escapes `\"`, `` \` ``, `\$` and `\\`. Field codes are then expanded for the files or URLs being
+//! opened:
+//!
+//! * `%f` and `%u` take a single file or URL, so the application is started once for each
+//! * `%F` and `%U` take all of them in one command line
+//! * `%i` becomes `--icon ICON` when the entry has an icon
+//! * `%c` becomes the entry's name and `%k` the location of its .desktop file
+//! * `%%` is a literal `%`, and the deprecated `%d %D %n %N %v %m` are dropped
+//!
+//! Codes that take files get local paths, converting `file://` URLs back to paths, while codes
+//! that take URLs get `file://` URLs for local paths.
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::entry::DesktopEntry;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ExecError {
+    #[error("the entry has no Exec key")]
+    Missing,
+
+    #[error("Exec has no program to run")]
+    Empty,
+
+    #[error("Exec has an unterminated quoted argument")]
+    UnterminatedQuote,
+}
+
+/// A file or URL to open with an application
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Path(PathBuf),
+    Url(String),
+}
+
+impl Target {
+    /// Interpret a command line argument, which is a URL if it starts with a scheme and a path
+    /// otherwise. Relative paths are made absolute, since the application may run elsewhere.
+    pub fn new(arg: &str) -> Target {
+        if has_scheme(arg) {
+            return Target::Url(arg.into());
+        }
+
+        let path = Path::new(arg);
+        if path.is_absolute() {
+            Target::Path(path.into())
+        } else {
+            Target::Path(env::current_dir().map_or_else(|_| path.into(), |dir| dir.join(path)))
+        }
+    }
+
+    /// The local path of the target, if it has one
+    pub fn to_path(&self) -> Option<PathBuf> {
+        match self {
+            Target::Path(path) => Some(path.clone()),
+            Target::Url(url) => {
+                let rest = url.strip_prefix("file://")?;
+                let path = match rest.find('/') {
+                    Some(idx) if idx == 0 || &rest[..idx] == "localhost" => &rest[idx..],
+                    _ => return None,
+                };
+
+                Some(PathBuf::from(percent_decode(path)))
+            },
+        }
+    }
+
+    pub fn to_url(&self) -> String {
+        match self {
+            Target::Path(path) => format!("file://{}", percent_encode(&path.to_string_lossy())),
+            Target::Url(url) => url.clone(),
+        }
+    }
+}
+
+fn has_scheme(arg: &str) -> bool {
+    match arg.find(':') {
+        Some(idx) if idx > 1 => {
+            let scheme = &arg[..idx];
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+.-".contains(c))
+        },
+        _ => false,
+    }
+}
+
+fn percent_encode(path: &str) -> String {
+    let mut res = String::with_capacity(path.len());
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => res.push(b as char),
+            b => res.push_str(&format!("%{:02X}", b)),
+        }
+    }
+
+    res
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+
+    while idx < bytes.len() {
+        let hex = bytes.get(idx + 1..idx + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[idx], hex) {
+            (b'%', Some(b)) => {
+                res.push(b);
+                idx += 3;
+            },
+            (b, _) => {
+                res.push(b);
+                idx += 1;
+            },
+        }
+    }
+
+    String::from_utf8_lossy(&res).into_owned()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Code(char),
+}
+
+/// A parsed `Exec` value
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exec {
+    args: Vec<Vec<Part>>,
+}
+
+impl Exec {
+    pub fn from_entry(entry: &DesktopEntry) -> Result<Exec, ExecError> {
+        Exec::parse(&entry.get("Exec").ok_or(ExecError::Missing)?)
+    }
+
+    /// Parse an `Exec` value whose string escapes have already been resolved
+    pub fn parse(exec: &str) -> Result<Exec, ExecError> {
+        let mut args = Vec::new();
+        let mut chars = exec.chars().peekable();
+
+        loop {
+            while chars.peek() == Some(&' ') {
+                chars.next();
+            }
+
+            if chars.peek().is_none() {
+                break;
+            }
+
+            let mut parts = Vec::new();
+            let mut literal = String::new();
+            let mut quoted = false;
+
+            while let Some(c) = chars.next() {
+                match c {
+                    ' ' if !quoted => break,
+                    '"' => quoted = !quoted,
+                    '\\' if quoted => match chars.next() {
+                        Some(c) => literal.push(c),
+                        None => return Err(ExecError::UnterminatedQuote),
+                    },
+                    '%' => match chars.next() {
+                        Some('%') | None => literal.push('%'),
+                        Some(code) => {
+                            if !literal.is_empty() {
+                                parts.push(Part::Literal(std::mem::take(&mut literal)));
+                            }
+
+                            parts.push(Part::Code(code));
+                        },
+                    },
+                    c => literal.push(c),
+                }
+            }
+
+            if quoted {
+                return Err(ExecError::UnterminatedQuote);
+            }
+
+            if !literal.is_empty() || parts.is_empty() {
+                parts.push(Part::Literal(literal));
+            }
+
+            args.push(parts);
+        }
+
+        match args.first() {
+            Some(parts) if parts.iter().any(|part| *part != Part::Literal(String::new())) => Ok(Exec { args }),
+            _ => Err(ExecError::Empty),
+        }
+    }
+
+    /// The program the command runs, unless it's made from field codes
+    pub fn program(&self) -> Option<String> {
+        self.args[0].iter()
+            .map(|part| match part {
+                Part::Literal(s) => Some(s.as_str()),
+                Part::Code(..) => None,
+            })
+            .collect()
+    }
+
+    /// The arguments before expansion, with field codes written as in the `Exec` value
+    pub fn args(&self) -> Vec<String> {
+        self.args.iter()
+            .map(|parts| parts.iter()
+                .map(|part| match part {
+                    Part::Literal(s) => s.replace('%', "%%"),
+                    Part::Code(code) => format!("%{}", code),
+                })
+                .collect())
+            .collect()
+    }
+
+    fn has_code(&self, code: char) -> bool {
+        self.args.iter().flatten().any(|part| *part == Part::Code(code))
+    }
+
+    /// Expand the field codes for the given targets, returning a command line for each instance
+    /// of the application to start. `lang` chooses the name used for `%c`.
+    pub fn expand(&self, targets: &[Target], entry: &DesktopEntry, lang: Option<&str>) -> Vec<Vec<String>> {
+        let takes_urls = self.has_code('u');
+        if !takes_urls && !self.has_code('f') {
+            return vec![self.expand_instance(targets, entry, lang)];
+        }
+
+        // One file or URL per instance, where only local files can be opened through `%f`
+        let targets = targets.iter()
+            .filter(|target| takes_urls || target.to_path().is_some())
+            .collect::<Vec<_>>();
+
+        if targets.is_empty() {
+            return vec![self.expand_instance(&[], entry, lang)];
+        }
+
+        targets.into_iter()
+            .map(|target| self.expand_instance(std::slice::from_ref(target), entry, lang))
+            .collect()
+    }
+
+    fn expand_instance(&self, targets: &[Target], entry: &DesktopEntry, lang: Option<&str>) -> Vec<String> {
+        let paths = || targets.iter().filter_map(Target::to_path).map(|path| path.to_string_lossy().into_owned());
+        let urls = || targets.iter().map(Target::to_url);
+
+        let mut argv = Vec::new();
+        for parts in &self.args {
+            // Codes that stand for several arguments, or none, have to fill a whole argument
+            let whole = match parts.as_slice() {
+                [Part::Code('F')] => Some(paths().collect()),
+                [Part::Code('U')] => Some(urls().collect()),
+                [Part::Code('i')] => Some(entry.get("Icon")
+                    .filter(|icon| !icon.is_empty())
+                    .map_or(vec![], |icon| vec!["--icon".to_string(), icon])),
+                [Part::Code('f')] => Some(paths().take(1).collect()),
+                [Part::Code('u')] => Some(urls().take(1).collect()),
+                [Part::Code(code)] if !"ck".contains(*code) => Some(vec![]),
+                _ => None,
+            };
+
+            if let Some(args) = whole {
+                argv.extend(args);
+                continue;
+            }
+
+            let mut arg = String::new();
+            for part in parts {
+                match part {
+                    Part::Literal(s) => arg.push_str(s),
+                    Part::Code('f') | Part::Code('F') => arg.push_str(&paths().next().unwrap_or_default()),
+                    Part::Code('u') | Part::Code('U') => arg.push_str(&urls().next().unwrap_or_default()),
+                    Part::Code('c') => arg.push_str(&entry.get_localized("Name", lang).unwrap_or_default()),
+                    Part::Code('k') => arg.push_str(&entry.path.to_string_lossy()),
+                    Part::Code(..) => (),
+                }
+            }
+
+            argv.push(arg);
+        }
+
+        argv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(exec: &str, targets: &[&str]) -> Vec<Vec<String>> {
+        let entry = DesktopEntry::parse("/apps/test.desktop", "[Desktop Entry]\nName=Test App\nIcon=test\n").unwrap();
+        let targets = targets.iter().map(|t| Target::new(t)).collect::<Vec<_>>();
+
+        Exec::parse(exec).unwrap().expand(&targets, &entry, None)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(expand(r#"sh -c "echo \"\$HOME\" \\ %%" %%"#, &[]), vec![vec!["sh", "-c", r#"echo "$HOME" \ %"#, "%"]]);
+        assert_eq!(expand(r#"prog "" x"#, &[]), vec![vec!["prog", "", "x"]]);
+        assert_eq!(Exec::parse(r#"prog "unterminated"#), Err(ExecError::UnterminatedQuote));
+        assert_eq!(Exec::parse("  "), Err(ExecError::Empty));
+        assert_eq!(Exec::parse(r#""/opt/my app/run" %U"#).unwrap().program().unwrap(), "/opt/my app/run");
+        assert_eq!(Exec::parse(r#""/opt/my app/run" --title="100%% %c" %U"#).unwrap().args(),
+            vec!["/opt/my app/run", "--title=100%% %c", "%U"]);
+    }
+
+    #[test]
+    fn test_expand() {
+        assert_eq!(expand("app %U", &["/tmp/a b", "https://example.com"]),
+            vec![vec!["app", "file:///tmp/a%20b", "https://example.com"]]);
+        assert_eq!(expand("app %f", &["file:///tmp/a%20b", "https://example.com", "/tmp/c"]),
+            vec![vec!["app", "/tmp/a b"], vec!["app", "/tmp/c"]]);
+        assert_eq!(expand("app %F", &["https://example.com", "/tmp/c"]), vec![vec!["app", "/tmp/c"]]);
+        assert_eq!(expand("app %u --flag", &[]), vec![vec!["app", "--flag"]]);
+        assert_eq!(expand("app %i --name=%c %k %d %m", &[]),
+            vec![vec!["app", "--icon", "test", "--name=Test App", "/apps/test.desktop"]]);
+    }
+
+    #[test]
+    fn test_target() {
+        assert_eq!(Target::new("mailto:someone@example.com"), Target::Url("mailto:someone@example.com".into()));
+        assert_eq!(Target::new("/tmp/x:y"), Target::Path("/tmp/x:y".into()));
+        assert_eq!(Target::Url("file://localhost/tmp/%C3%A9".into()).to_path(), Some("/tmp/é".into()));
+        assert_eq!(Target::Url("file://host/tmp".into()).to_path(), None);
+        assert_eq!(Target::Path("/tmp/é".into()).to_url(), "file:///tmp/%C3%A9");
+    }
+}
@@ -0,0 +1,396 @@
+//! Filter expressions for selecting entries by their values
+//!
+//! ```text
+//! categories has Game && !terminal
+//! exec ~ /flatpak/ || X-Flatpak
+//! (name ~ /^gnome/i || comment[de] == 'Dateien') && type != Link
+//! ```
+//!
+//! Operands are named like template sources, optionally with a locale (`comment[de]`), and can be
+//! quoted when they contain spaces (`'Desktop Action new/Exec'`). A bare operand is true when the
+//! value is present and not `false`, which also tests whether a key like
+//! `X-GNOME-UsesNotifications` exists. Comparisons are:
+//!
+//! * `==`, `!=` compare the whole value as text
+//! * `~`, `!~` match a regex written `/.../`, optionally followed by `i` to ignore case; a list
+//!   matches when any of its items does
+//! * `has` checks whether a list contains an item
+//!
+//! Comparisons combine with `!`, `&&`, `||` and parentheses, with `&&` binding tighter. Values are
+//! bare words or strings quoted with `'` or `"`.
+
+use std::error::Error;
+use std::fmt;
+
+use regex::{Regex, RegexBuilder};
+
+use crate::entry::split_list;
+use crate::output::Field;
+use crate::template::Source;
+
+/// A filter that couldn't be parsed, with the position of the problem in the expression
+#[derive(Debug, PartialEq)]
+pub struct FilterError {
+    pub expr: String,
+    /// Character offset of the problem in `expr`
+    pub pos: usize,
+    pub message: String,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "invalid filter at column {}: {}", self.pos + 1, self.message)?;
+        writeln!(f, "  {}", self.expr)?;
+        write!(f, "  {}^", " ".repeat(self.pos))
+    }
+}
+
+impl Error for FilterError {}
+
+#[derive(Debug)]
+enum Expr {
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Present(usize),
+    Equals(usize, String),
+    Has(usize, String),
+    Matches(usize, Regex),
+}
+
+#[derive(Debug)]
+pub struct Filter {
+    expr: Expr,
+    sources: Vec<Source>,
+}
+
+impl Filter {
+    pub fn parse(input: &str) -> Result<Filter, FilterError> {
+        let tokens = tokenize(input)?;
+        let mut parser = FilterParser { input, tokens, pos: 0, sources: Vec::new() };
+
+        let expr = parser.parse_or()?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(parser.error(token.pos, "expected `&&`, `||` or the end of the filter"));
+        }
+
+        Ok(Filter { expr, sources: parser.sources })
+    }
+
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
+    }
+
+    /// Evaluate the filter, resolving every source through `resolve`
+    pub fn matches<F: Fn(&Source) -> Field>(&self, resolve: F) -> bool {
+        let fields = self.sources.iter()
+            .map(&resolve)
+            .collect::<Vec<Field>>();
+
+        evaluate(&self.expr, &fields)
+    }
+}
+
+fn evaluate(expr: &Expr, fields: &[Field]) -> bool {
+    match expr {
+        Expr::Not(expr) => !evaluate(expr, fields),
+        Expr::And(a, b) => evaluate(a, fields) && evaluate(b, fields),
+        Expr::Or(a, b) => evaluate(a, fields) || evaluate(b, fields),
+        Expr::Present(source) => fields[*source].is_present(),
+        Expr::Equals(source, value) => fields[*source].to_text() == *value,
+        Expr::Has(source, value) => match &fields[*source] {
+            Field::List(items) => items.contains(value),
+            Field::Empty => false,
+            field => split_list(&field.to_text()).contains(value),
+        },
+        Expr::Matches(source, regex) => match &fields[*source] {
+            Field::List(items) => items.iter().any(|item| regex.is_match(item)),
+            Field::Empty => false,
+            field => regex.is_match(&field.to_text()),
+        },
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Word(String),
+    Str(String),
+    Regex(String, bool),
+    Not,
+    And,
+    Or,
+    Equal,
+    NotEqual,
+    Match,
+    NotMatch,
+    OpenParen,
+    CloseParen,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    pos: usize,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || "-_:/.@+*[]".contains(c)
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, FilterError> {
+    let chars = input.chars().collect::<Vec<char>>();
+    let error = |pos: usize, message: &str| FilterError { expr: input.into(), pos, message: message.into() };
+
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        let start = pos;
+        let pair = (chars[pos], chars.get(pos + 1).copied());
+
+        let kind = match pair {
+            (c, _) if c.is_whitespace() => {
+                pos += 1;
+                continue;
+            },
+            ('&', Some('&')) => TokenKind::And,
+            ('|', Some('|')) => TokenKind::Or,
+            ('=', Some('=')) => TokenKind::Equal,
+            ('!', Some('=')) => TokenKind::NotEqual,
+            ('!', Some('~')) => TokenKind::NotMatch,
+            ('!', _) => TokenKind::Not,
+            ('~', _) => TokenKind::Match,
+            ('(', _) => TokenKind::OpenParen,
+            (')', _) => TokenKind::CloseParen,
+            (quote @ '\'', _) | (quote @ '"', _) | (quote @ '/', _) => {
+                let mut value = String::new();
+                pos += 1;
+
+                loop {
+                    match chars.get(pos) {
+                        Some('\\') if chars.get(pos + 1) == Some(&quote) => {
+                            value.push(quote);
+                            pos += 2;
+                        },
+                        Some(c) if *c == quote => break,
+                        Some(c) => {
+                            value.push(*c);
+                            pos += 1;
+                        },
+                        None => return Err(error(start, &format!("missing closing `{}`", quote))),
+                    }
+                }
+
+                pos += 1;
+                if quote == '/' {
+                    let ignore_case = chars.get(pos) == Some(&'i');
+                    if ignore_case {
+                        pos += 1;
+                    }
+
+                    tokens.push(Token { kind: TokenKind::Regex(value, ignore_case), pos: start });
+                } else {
+                    tokens.push(Token { kind: TokenKind::Str(value), pos: start });
+                }
+
+                continue;
+            },
+            (c, _) if is_word_char(c) => {
+                while chars.get(pos).is_some_and(|c| is_word_char(*c)) {
+                    pos += 1;
+                }
+
+                let word = chars[start..pos].iter().collect();
+                tokens.push(Token { kind: TokenKind::Word(word), pos: start });
+                continue;
+            },
+            (c, _) => return Err(error(start, &format!("unexpected `{}`", c))),
+        };
+
+        pos += match kind {
+            TokenKind::And | TokenKind::Or | TokenKind::Equal | TokenKind::NotEqual | TokenKind::NotMatch => 2,
+            _ => 1,
+        };
+
+        tokens.push(Token { kind, pos: start });
+    }
+
+    Ok(tokens)
+}
+
+struct FilterParser<'a> {
+    input: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+    sources: Vec<Source>,
+}
+
+impl<'a> FilterParser<'a> {
+    fn parse_or(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.parse_and()?;
+        while self.eat(&TokenKind::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.parse_unary()?;
+        while self.eat(&TokenKind::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_unary()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, FilterError> {
+        if self.eat(&TokenKind::Not) {
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+
+        if self.eat(&TokenKind::OpenParen) {
+            let open = self.tokens[self.pos - 1].pos;
+            let expr = self.parse_or()?;
+            if !self.eat(&TokenKind::CloseParen) {
+                return Err(self.error(open, "missing `)` for this `(`"));
+            }
+
+            return Ok(expr);
+        }
+
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, FilterError> {
+        let source = match self.next() {
+            Some(Token { kind: TokenKind::Word(word), pos }) | Some(Token { kind: TokenKind::Str(word), pos }) => self.source(&word, pos)?,
+            Some(token) => return Err(self.error(token.pos, "expected a column or key name")),
+            None => return Err(self.error(self.end(), "expected a column or key name")),
+        };
+
+        let op = match self.peek() {
+            Some(Token { kind: TokenKind::Word(word), .. }) if word == "has" => TokenKind::Word(word),
+            Some(Token { kind: kind @ TokenKind::Equal, .. }) | Some(Token { kind: kind @ TokenKind::NotEqual, .. })
+            | Some(Token { kind: kind @ TokenKind::Match, .. }) | Some(Token { kind: kind @ TokenKind::NotMatch, .. }) => kind,
+            _ => return Ok(Expr::Present(source)),
+        };
+
+        self.pos += 1;
+        let (value, ignore_case, pos) = match self.next() {
+            Some(Token { kind: TokenKind::Word(value), pos }) | Some(Token { kind: TokenKind::Str(value), pos }) => (value, None, pos),
+            Some(Token { kind: TokenKind::Regex(pattern, ignore_case), pos }) => (pattern, Some(ignore_case), pos),
+            Some(token) => return Err(self.error(token.pos, "expected a value")),
+            None => return Err(self.error(self.end(), "expected a value")),
+        };
+
+        Ok(match op {
+            TokenKind::Match | TokenKind::NotMatch => {
+                let regex = RegexBuilder::new(&value)
+                    .case_insensitive(ignore_case.unwrap_or(false))
+                    .build()
+                    .map_err(|err| self.error(pos, &format!("invalid regex: {}", err)))?;
+
+                let expr = Expr::Matches(source, regex);
+                if op == TokenKind::NotMatch { Expr::Not(Box::new(expr)) } else { expr }
+            },
+            _ if ignore_case.is_some() => return Err(self.error(pos, "a regex can only be used with `~` or `!~`")),
+            TokenKind::Equal => Expr::Equals(source, value),
+            TokenKind::NotEqual => Expr::Not(Box::new(Expr::Equals(source, value))),
+            _ => Expr::Has(source, value),
+        })
+    }
+
+    /// Register the source named by a word, splitting off a `[locale]` suffix
+    fn source(&mut self, word: &str, pos: usize) -> Result<usize, FilterError> {
+        let (name, locale) = match word.find('[') {
+            Some(idx) if word.ends_with(']') => (&word[..idx], Some(word[idx + 1..word.len() - 1].to_string())),
+            Some(idx) => return Err(self.error(pos + word[..idx].chars().count(), "unterminated locale; expected `]`")),
+            None => (word, None),
+        };
+
+        let existing = self.sources.iter()
+            .find(|source| source.name == name && source.locale == locale)
+            .map(|source| source.idx);
+
+        Ok(existing.unwrap_or_else(|| {
+            let idx = self.sources.len();
+            self.sources.push(Source { idx, name: name.into(), locale });
+            idx
+        }))
+    }
+
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        let matched = self.peek().is_some_and(|token| token.kind == *kind);
+        if matched {
+            self.pos += 1;
+        }
+
+        matched
+    }
+
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek();
+        self.pos += 1;
+        token
+    }
+
+    fn end(&self) -> usize {
+        self.input.chars().count()
+    }
+
+    fn error(&self, pos: usize, message: &str) -> FilterError {
+        FilterError { expr: self.input.into(), pos, message: message.into() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(filter: &str, values: &[(&str, Field)]) -> bool {
+        Filter::parse(filter).unwrap().matches(|source| values.iter()
+            .find(|(name, _)| *name == source.name)
+            .map_or(Field::Empty, |(_, field)| field.clone()))
+    }
+
+    #[test]
+    fn test_matches() {
+        let values = [
+            ("categories", Field::List(vec!["Game".into(), "ArcadeGame".into()])),
+            ("terminal", Field::Bool(false)),
+            ("exec", Field::Text("flatpak run org.example.Game".into())),
+            ("X-Flatpak", Field::Text("org.example.Game".into())),
+            ("key:MimeType", Field::Text("image/png;image/jpeg;".into())),
+        ];
+
+        assert!(matches("categories has Game && !terminal", &values));
+        assert!(matches("exec ~ /flatpak/", &values));
+        assert!(matches("exec ~ /FLATPAK/i && exec !~ 'steam'", &values));
+        assert!(matches("key:MimeType has image/png", &values));
+        assert!(matches("X-Flatpak && !X-Missing", &values));
+        assert!(matches("'X-Flatpak' == org.example.Game", &values));
+        assert!(matches("terminal || (exec == 'flatpak run org.example.Game')", &values));
+        assert!(!matches("categories has Arcade || terminal", &values));
+        assert!(!matches("missing ~ /./ || missing has x", &values));
+    }
+
+    #[test]
+    fn test_errors() {
+        let pos = |filter: &str| Filter::parse(filter).unwrap_err().pos;
+
+        assert_eq!(pos("categories has"), 14);
+        assert_eq!(pos("exec ~ /(/"), 7);
+        assert_eq!(pos("(terminal && exec"), 0);
+        assert_eq!(pos("name == 'x' exec"), 12);
+        assert_eq!(pos("name & exec"), 5);
+        assert_eq!(pos("name == /x/"), 8);
+
+        let err = Filter::parse("a && ").unwrap_err();
+        assert_eq!(err.to_string(), "invalid filter at column 6: expected a column or key name\n  a && \n       ^");
+    }
+}
@@ -0,0 +1,311 @@
+//! Finding the file for an `Icon` key, following the Icon Theme spec
+//!
+//! An icon name is looked up in the selected theme, then in the themes it inherits from, and
+//! finally in `hicolor`, which every theme falls back to. Within a theme, a directory made for
+//! the requested size and scale wins, and failing that the directory whose size is closest.
+//! Icons that no theme has are looked for directly in the base directories, which include
+//! `/usr/share/pixmaps`. An `Icon` value that's an absolute path is used as it is.
+//!
+//! Themes are read from `index.theme`, which uses the same syntax as .desktop files. A theme can
+//! be spread over several base directories, like `~/.local/share/icons/hicolor` and
+//! `/usr/share/icons/hicolor`, and all of them are searched.
+
+use std::cell::OnceCell;
+use std::env;
+use std::path::{Path, PathBuf};
+
+use crate::entry::{DesktopEntry, Group};
+
+/// Extensions of icon files, in order of preference
+const EXTENSIONS: &[&str] = &["png", "svg", "xpm"];
+
+/// The theme every theme falls back to
+const FALLBACK_THEME: &str = "hicolor";
+
+const THEME_GROUP: &str = "Icon Theme";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SizeType {
+    Fixed,
+    Scalable,
+    Threshold,
+}
+
+/// A directory of a theme that holds icons of one size
+#[derive(Debug, Clone, PartialEq)]
+struct Directory {
+    /// Path relative to the theme directory, like `48x48/apps`
+    name: String,
+    size: u32,
+    scale: u32,
+    kind: SizeType,
+    min_size: u32,
+    max_size: u32,
+    threshold: u32,
+}
+
+impl Directory {
+    fn from_group(group: &Group) -> Option<Directory> {
+        let number = |key: &str| group.get(key).and_then(|value| value.trim().parse::<u32>().ok());
+
+        let size = number("Size")?;
+        let kind = match group.get("Type").as_deref() {
+            Some("Fixed") => SizeType::Fixed,
+            Some("Scalable") => SizeType::Scalable,
+            _ => SizeType::Threshold,
+        };
+
+        Some(Directory {
+            name: group.name.clone(),
+            size,
+            scale: number("Scale").unwrap_or(1),
+            kind,
+            min_size: number("MinSize").unwrap_or(size),
+            max_size: number("MaxSize").unwrap_or(size),
+            threshold: number("Threshold").unwrap_or(2),
+        })
+    }
+
+    fn matches_size(&self, size: u32, scale: u32) -> bool {
+        if self.scale != scale {
+            return false;
+        }
+
+        match self.kind {
+            SizeType::Fixed => self.size == size,
+            SizeType::Scalable => self.min_size <= size && size <= self.max_size,
+            SizeType::Threshold => self.size.saturating_sub(self.threshold) <= size && size <= self.size + self.threshold,
+        }
+    }
+
+    /// How far the icons of this directory are from the wanted size, in pixels
+    fn size_distance(&self, size: u32, scale: u32) -> u32 {
+        let wanted = size * scale;
+        let (min, max) = match self.kind {
+            SizeType::Fixed => (self.size, self.size),
+            SizeType::Scalable => (self.min_size, self.max_size),
+            SizeType::Threshold => (self.size.saturating_sub(self.threshold), self.size + self.threshold),
+        };
+
+        if wanted < min * self.scale {
+            min * self.scale - wanted
+        } else {
+            wanted.saturating_sub(max * self.scale)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Theme {
+    name: String,
+    inherits: Vec<String>,
+    /// Directories of the theme, each with the places it exists in the base directories
+    directories: Vec<(Directory, Vec<PathBuf>)>,
+}
+
+impl Theme {
+    /// Load a theme from the first base directory with its `index.theme`
+    fn load(name: &str, base_dirs: &[PathBuf]) -> Option<Theme> {
+        let index = base_dirs.iter()
+            .map(|base| base.join(name).join("index.theme"))
+            .find(|index| index.is_file())?;
+        let index = DesktopEntry::read(&index).ok()?;
+
+        let roots = base_dirs.iter()
+            .map(|base| base.join(name))
+            .filter(|root| root.is_dir())
+            .collect::<Vec<PathBuf>>();
+
+        Theme::from_index(name, &index, |directory| roots.iter()
+            .map(|root| root.join(&directory.name))
+            .filter(|path| path.is_dir())
+            .collect())
+    }
+
+    /// Build a theme from its `index.theme`, where `locate` finds the places a directory exists
+    fn from_index<F>(name: &str, index: &DesktopEntry, locate: F) -> Option<Theme>
+    where
+        F: Fn(&Directory) -> Vec<PathBuf>
+    {
+        let group = index.group(THEME_GROUP)?;
+        let names = comma_list(group, "Directories").into_iter()
+            .chain(comma_list(group, "ScaledDirectories"));
+
+        let mut directories = Vec::new();
+        for name in names {
+            if directories.iter().any(|(directory, _): &(Directory, _)| directory.name == name) {
+                continue;
+            }
+
+            if let Some(directory) = index.group(&name).and_then(Directory::from_group) {
+                let paths = locate(&directory);
+                directories.push((directory, paths));
+            }
+        }
+
+        Some(Theme {
+            name: name.into(),
+            inherits: comma_list(group, "Inherits"),
+            directories,
+        })
+    }
+
+    /// Find an icon in a directory made for the size, or else in the closest one
+    fn lookup(&self, icon: &str, size: u32, scale: u32) -> Option<PathBuf> {
+        let existing = self.directories.iter()
+            .filter(|(_, paths)| !paths.is_empty());
+
+        for (_, paths) in existing.clone().filter(|(directory, _)| directory.matches_size(size, scale)) {
+            if let Some(path) = find_file(paths, icon) {
+                return Some(path);
+            }
+        }
+
+        existing
+            .filter_map(|(directory, paths)| Some((directory.size_distance(size, scale), find_file(paths, icon)?)))
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, path)| path)
+    }
+}
+
+/// Lists in `index.theme` are separated by commas, unlike the semicolons of .desktop files
+fn comma_list(group: &Group, key: &str) -> Vec<String> {
+    group.get(key)
+        .map(|value| value.split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect())
+        .unwrap_or_default()
+}
+
+/// The icon file in the first of `dirs` that has it, trying each extension in turn
+fn find_file(dirs: &[PathBuf], icon: &str) -> Option<PathBuf> {
+    dirs.iter()
+        .flat_map(|dir| EXTENSIONS.iter().map(move |ext| dir.join(format!("{}.{}", icon, ext))))
+        .find(|path| path.is_file())
+}
+
+/// The directories icon themes are found in, most important first
+pub fn base_dirs() -> Vec<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".icons")).into_iter()
+        .chain(crate::data_dirs().into_iter().map(|dir| dir.join("icons")))
+        .chain(std::iter::once(PathBuf::from("/usr/share/pixmaps")))
+        .collect()
+}
+
+/// Resolves icon names to files for one theme and size. The themes are only read the first time
+/// an icon is looked up.
+#[derive(Debug)]
+pub struct IconFinder {
+    theme: String,
+    size: u32,
+    scale: u32,
+    base_dirs: Vec<PathBuf>,
+    themes: OnceCell<Vec<Theme>>,
+}
+
+impl IconFinder {
+    pub fn new(theme: &str, size: u32, scale: u32) -> IconFinder {
+        IconFinder::with_base_dirs(theme, size, scale, base_dirs())
+    }
+
+    pub fn with_base_dirs(theme: &str, size: u32, scale: u32, base_dirs: Vec<PathBuf>) -> IconFinder {
+        IconFinder { theme: theme.into(), size, scale, base_dirs, themes: OnceCell::new() }
+    }
+
+    /// The theme and every theme it inherits from, depth first, ending with `hicolor`
+    fn themes(&self) -> &[Theme] {
+        self.themes.get_or_init(|| {
+            let mut themes: Vec<Theme> = Vec::new();
+            let mut pending = vec![self.theme.clone()];
+
+            while let Some(name) = pending.pop() {
+                if themes.iter().any(|theme| theme.name == name) {
+                    continue;
+                }
+
+                if let Some(theme) = Theme::load(&name, &self.base_dirs) {
+                    pending.extend(theme.inherits.iter().rev().cloned());
+                    themes.push(theme);
+                }
+
+                if pending.is_empty() && !themes.iter().any(|theme| theme.name == FALLBACK_THEME) && name != FALLBACK_THEME {
+                    pending.push(FALLBACK_THEME.into());
+                }
+            }
+
+            themes
+        })
+    }
+
+    /// Find the file for an `Icon` value. Extensions on icon names aren't allowed by the spec,
+    /// but are common enough that they're ignored.
+    pub fn find(&self, icon: &str) -> Option<PathBuf> {
+        if icon.is_empty() {
+            return None;
+        }
+
+        let path = Path::new(icon);
+        if path.is_absolute() {
+            return Some(path.to_path_buf()).filter(|path| path.is_file());
+        }
+
+        let name = match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if EXTENSIONS.contains(&ext) => &icon[..icon.len() - ext.len() - 1],
+            _ => icon,
+        };
+
+        self.themes().iter()
+            .find_map(|theme| theme.lookup(name, self.size, self.scale))
+            .or_else(|| find_file(&self.base_dirs, name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INDEX: &str = "[Icon Theme]\n\
+        Name=Test\n\
+        Inherits=Adwaita,hicolor\n\
+        Directories=16x16/apps,48x48/apps,scalable/apps,256x256/apps\n\
+        ScaledDirectories=48x48@2/apps\n\
+        \n\
+        [16x16/apps]\nSize=16\nType=Fixed\n\
+        [48x48/apps]\nSize=48\n\
+        [48x48@2/apps]\nSize=48\nScale=2\nType=Fixed\n\
+        [scalable/apps]\nSize=128\nType=Scalable\nMinSize=8\nMaxSize=512\n\
+        [256x256/apps]\nSize=256\nType=Fixed\n";
+
+    fn theme() -> Theme {
+        let index = DesktopEntry::parse("index.theme", INDEX).unwrap();
+        Theme::from_index("test", &index, |directory| vec![PathBuf::from(&directory.name)]).unwrap()
+    }
+
+    #[test]
+    fn test_index() {
+        let theme = theme();
+        let names = theme.directories.iter().map(|(directory, _)| directory.name.as_str()).collect::<Vec<_>>();
+
+        assert_eq!(theme.inherits, vec!["Adwaita", "hicolor"]);
+        assert_eq!(names, vec!["16x16/apps", "48x48/apps", "scalable/apps", "256x256/apps", "48x48@2/apps"]);
+    }
+
+    #[test]
+    fn test_size() {
+        let theme = theme();
+        let directory = |name: &str| &theme.directories.iter().find(|(directory, _)| directory.name == name).unwrap().0;
+
+        assert!(directory("48x48/apps").matches_size(50, 1));
+        assert!(!directory("48x48/apps").matches_size(48, 2));
+        assert!(directory("48x48@2/apps").matches_size(48, 2));
+        assert!(directory("scalable/apps").matches_size(300, 1));
+        assert!(!directory("16x16/apps").matches_size(24, 1));
+
+        assert_eq!(directory("16x16/apps").size_distance(24, 1), 8);
+        assert_eq!(directory("48x48/apps").size_distance(24, 1), 22);
+        assert_eq!(directory("48x48@2/apps").size_distance(64, 1), 32);
+        assert_eq!(directory("scalable/apps").size_distance(600, 1), 88);
+    }
+}
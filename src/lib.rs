@@ -1,14 +1,39 @@
 #![feature(type_alias_impl_trait)]
 
+pub mod cache;
+pub mod collate;
+pub mod complete;
+pub mod config;
+pub mod entry;
+pub mod exec;
+pub mod filter;
+pub mod icon;
+pub mod menu;
+pub mod mime;
+pub mod mimeapps;
 mod parser;
+pub mod output;
+pub mod search;
+pub mod template;
+pub mod terminal;
+pub mod tryexec;
+pub mod watch;
 
-use std::collections::HashMap;
 use std::convert::AsRef;
-use std::fs::{read_dir, read_to_string};
+use std::env;
+use std::fs::read_dir;
 use std::path::{Path, PathBuf};
 
+use color_eyre::Result;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
 use shellexpand::tilde;
 
+pub use cache::Cache;
+pub use entry::DesktopEntry;
+pub use template::Template;
+pub use watch::Watcher;
+
 pub fn enumerate_desktop_files<S>(sources: S) -> Vec<PathBuf>
 where
     S: IntoIterator,
@@ -18,20 +43,164 @@ where
        .filter_map(|source| {
            source.as_ref().to_str()
                .map(|path| tilde(path).into_owned())
-               .and_then(|path| read_dir(path).ok())
        })
-       .map(|d| d
-           .filter_map(|e| e.ok()
-               .map(|e| e.path())))
-       .flatten()
+       .flat_map(list_desktop_files)
        .collect::<Vec<PathBuf>>()
 }
 
-pub fn get_file_properties<P: AsRef<Path>>(filename: P) -> HashMap<&'static str, String> {
-    let contents = read_to_string(filename).unwrap();
-    println!("contents: {}", contents);
+/// A base directory of the user, from its variable or else under `$HOME`
+fn user_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    match env::var_os(var) {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(fallback)),
+    }
+}
+
+/// The system base directories listed in a variable, or the default ones
+fn system_dirs(var: &str, default: &str) -> Vec<PathBuf> {
+    let dirs = env::var(var).ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| default.into());
+
+    dirs.split(':')
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// The XDG base directories for data, most important first: `$XDG_DATA_HOME`, then each of
+/// `$XDG_DATA_DIRS`, with the defaults from the base directory spec when they're unset
+pub fn data_dirs() -> Vec<PathBuf> {
+    user_dir("XDG_DATA_HOME", ".local/share").into_iter()
+        .chain(system_dirs("XDG_DATA_DIRS", "/usr/local/share:/usr/share"))
+        .collect()
+}
+
+/// `$XDG_CONFIG_HOME`, or `~/.config` when it's unset
+pub fn config_home() -> Option<PathBuf> {
+    user_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// The XDG base directories for configuration, most important first: `$XDG_CONFIG_HOME`, then
+/// each of `$XDG_CONFIG_DIRS`
+pub fn config_dirs() -> Vec<PathBuf> {
+    config_home().into_iter()
+        .chain(system_dirs("XDG_CONFIG_DIRS", "/etc/xdg"))
+        .collect()
+}
+
+/// Expand `~` in a list of source directories
+pub fn expand_sources<S>(sources: S) -> Vec<PathBuf>
+where
+    S: IntoIterator,
+    S::Item: AsRef<str>
+{
+    sources.into_iter()
+        .map(|source| PathBuf::from(tilde(source.as_ref()).into_owned()))
+        .collect()
+}
+
+/// The source directory a .desktop file was found in, which is the first one containing it
+pub fn source_dir<'a>(path: &Path, sources: &'a [PathBuf]) -> Option<&'a Path> {
+    sources.iter()
+        .map(PathBuf::as_path)
+        .find(|source| path.starts_with(source))
+}
+
+/// The desktop file ID of a .desktop file: its path relative to the source directory, with `/`
+/// replaced by `-`. Files outside every source are identified by their file name.
+pub fn desktop_id(path: &Path, sources: &[PathBuf]) -> String {
+    let relative = source_dir(path, sources)
+        .and_then(|source| path.strip_prefix(source).ok())
+        .unwrap_or_else(|| path.file_name().map_or(path, Path::new));
+
+    relative.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Every .desktop file with a desktop file ID, in the order of the source directories. The first
+/// is the one in effect, and it shadows the rest.
+pub fn files_with_id(id: &str, sources: &[PathBuf]) -> Vec<PathBuf> {
+    sources.iter()
+        .flat_map(|source| list_desktop_files(source).into_iter()
+            .filter(move |path| desktop_id(path, std::slice::from_ref(source)) == id))
+        .collect()
+}
+
+/// List the .desktop files in one source directory and its subdirectories. Files in a directory
+/// come in `read_dir` order, followed by the contents of each of its subdirectories.
+pub fn list_desktop_files<P: AsRef<Path>>(dir: P) -> Vec<PathBuf> {
+    let (mut files, subdirs) = list_dir(dir);
+    for subdir in subdirs {
+        files.extend(list_desktop_files(subdir));
+    }
+
+    files
+}
+
+/// List the .desktop files and the subdirectories directly inside a directory. Symlinks to
+/// directories aren't followed, so a link back up the tree can't make a scan loop forever.
+pub(crate) fn list_dir<P: AsRef<Path>>(dir: P) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let mut files = Vec::new();
+    let mut subdirs = Vec::new();
+
+    for entry in read_dir(dir).into_iter().flatten().filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            subdirs.push(path);
+        } else if path.extension().is_some_and(|ext| ext == "desktop") {
+            files.push(path);
+        }
+    }
+
+    (files, subdirs)
+}
+
+/// Read and parse every file, spreading the work over `jobs` threads (or one per CPU when `jobs`
+/// is 0). Results come back in the same order as `files`, so the output never depends on the
+/// thread count.
+pub fn read_desktop_entries(files: &[PathBuf], jobs: usize) -> Result<Vec<Result<DesktopEntry>>> {
+    if jobs == 1 {
+        return Ok(files.iter().map(DesktopEntry::read).collect());
+    }
+
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()?;
+
+    Ok(pool.install(|| files.par_iter().map(DesktopEntry::read).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    parser::Parser::new("abcd");
-    
-    HashMap::new()
-}
\ No newline at end of file
+    #[test]
+    fn test_desktop_id() {
+        let sources = vec![PathBuf::from("/usr/share/applications"), PathBuf::from("/opt/apps")];
+
+        assert_eq!(desktop_id(Path::new("/usr/share/applications/kde4/dolphin.desktop"), &sources), "kde4-dolphin.desktop");
+        assert_eq!(desktop_id(Path::new("/opt/apps/vim.desktop"), &sources), "vim.desktop");
+        assert_eq!(desktop_id(Path::new("/tmp/other/vim.desktop"), &sources), "vim.desktop");
+    }
+
+    #[test]
+    fn test_files_with_id() {
+        let root = std::env::temp_dir().join(format!("lsapp-shadow-test-{}", std::process::id()));
+        let user = root.join("home/.local/share/applications");
+        let system = root.join("usr/share/applications");
+        for dir in &[&user, &system] {
+            std::fs::create_dir_all(dir).unwrap();
+            std::fs::write(dir.join("firefox.desktop"), "[Desktop Entry]\nName=Firefox\n").unwrap();
+        }
+
+        // The user's directory comes first, like it does in the XDG data directories
+        let sources = vec![user.clone(), system.clone()];
+        assert_eq!(files_with_id("firefox.desktop", &sources), vec![user.join("firefox.desktop"), system.join("firefox.desktop")]);
+        assert_eq!(source_dir(&system.join("firefox.desktop"), &sources), Some(system.as_path()));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
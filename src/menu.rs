@@ -0,0 +1,173 @@
+//! Input for launcher menus like rofi, wofi, dmenu and fuzzel
+//!
+//! Each menu reads one item per line, and each has its own way to attach an icon or data that
+//! isn't shown:
+//!
+//! - rofi's script mode takes options after a NUL, like `Firefox\0icon\x1ffirefox\x1finfo\x1f...`,
+//!   where `info` is handed back to the script in `$ROFI_INFO` and `meta` is searched but hidden
+//! - fuzzel's dmenu mode takes the same `\0icon\x1f...` suffix, but nothing else
+//! - wofi's dmenu mode, with `allow_images`, takes `img:PATH:text:Firefox`, where the icon must
+//!   be a file
+//! - dmenu only takes text
+//!
+//! The chosen line comes back from the menu, decorated or not, and is matched against the items
+//! to find the entry to launch.
+
+use std::str::FromStr;
+
+use thiserror::Error;
+
+pub const MODES: &[&str] = &["rofi", "wofi", "dmenu", "fuzzel"];
+
+/// Separates the options of a rofi item
+const FIELD_SEPARATOR: char = '\x1f';
+
+#[derive(Error, Debug)]
+#[error("unsupported mode `{0}`; use one of {}", MODES.join(", "))]
+pub struct UnknownMode(String);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Rofi,
+    Wofi,
+    Dmenu,
+    Fuzzel,
+}
+
+impl FromStr for Mode {
+    type Err = UnknownMode;
+
+    fn from_str(s: &str) -> Result<Mode, UnknownMode> {
+        match s {
+            "rofi" => Ok(Mode::Rofi),
+            "wofi" => Ok(Mode::Wofi),
+            "dmenu" => Ok(Mode::Dmenu),
+            "fuzzel" => Ok(Mode::Fuzzel),
+            _ => Err(UnknownMode(s.into())),
+        }
+    }
+}
+
+/// One line of a menu
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Item {
+    pub text: String,
+    /// An icon name or path; wofi only shows paths
+    pub icon: Option<String>,
+    /// Searched but not shown, for rofi
+    pub meta: Option<String>,
+    /// Handed back with the chosen item, for rofi
+    pub info: Option<String>,
+}
+
+impl Mode {
+    /// Whether icons have to be given as files rather than names from the icon theme
+    pub fn needs_icon_paths(self) -> bool {
+        self == Mode::Wofi
+    }
+
+    /// Options for the whole menu, printed before the items
+    pub fn header(self) -> Option<String> {
+        match self {
+            // Text typed into rofi that doesn't match an item has nothing to launch
+            Mode::Rofi => Some(format!("\0no-custom{}true", FIELD_SEPARATOR)),
+            _ => None,
+        }
+    }
+
+    /// Render an item as a line, without the line break
+    pub fn format(self, item: &Item) -> String {
+        let text = clean(&item.text);
+        let icon = item.icon.as_deref().filter(|icon| !icon.is_empty());
+
+        match self {
+            Mode::Rofi => {
+                let options = [("icon", icon), ("meta", item.meta.as_deref()), ("info", item.info.as_deref())]
+                    .iter()
+                    .filter_map(|(name, value)| Some(format!("{}{}{}", name, FIELD_SEPARATOR, clean(value.as_ref()?))))
+                    .collect::<Vec<String>>();
+
+                if options.is_empty() {
+                    text
+                } else {
+                    format!("{}\0{}", text, options.join(&FIELD_SEPARATOR.to_string()))
+                }
+            },
+            Mode::Fuzzel => match icon {
+                Some(icon) => format!("{}\0icon{}{}", text, FIELD_SEPARATOR, clean(icon)),
+                None => text,
+            },
+            Mode::Wofi => match icon {
+                Some(icon) => format!("img:{}:text:{}", clean(icon), text),
+                None => text,
+            },
+            Mode::Dmenu => text,
+        }
+    }
+
+    /// The text of a line the menu handed back, without any decoration
+    pub fn selected_text(self, line: &str) -> &str {
+        let line = line.trim_end_matches(&['\n', '\r'][..]);
+
+        match self {
+            Mode::Wofi => line.strip_prefix("img:")
+                .and_then(|rest| rest.find(":text:").map(|idx| &rest[idx + ":text:".len()..]))
+                .unwrap_or(line),
+            _ => line,
+        }
+    }
+
+    /// Find the index of the item a line handed back by the menu was made from
+    pub fn position(self, items: &[Item], line: &str) -> Option<usize> {
+        let text = self.selected_text(line);
+        items.iter().position(|item| clean(&item.text) == text)
+    }
+}
+
+/// Menus read one item per line, and rofi splits options on NUL and the field separator
+fn clean(value: &str) -> String {
+    value.chars()
+        .map(|c| match c {
+            '\n' | '\r' | '\0' | FIELD_SEPARATOR => ' ',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item() -> Item {
+        Item {
+            text: "Firefox\nWeb Browser".into(),
+            icon: Some("firefox".into()),
+            meta: Some("Internet;WWW".into()),
+            info: Some("/usr/share/applications/firefox.desktop".into()),
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        let plain = Item { text: "Vim".into(), ..Item::default() };
+
+        assert_eq!(Mode::Rofi.format(&item()),
+            "Firefox Web Browser\0icon\x1ffirefox\x1fmeta\x1fInternet;WWW\x1finfo\x1f/usr/share/applications/firefox.desktop");
+        assert_eq!(Mode::Rofi.format(&plain), "Vim");
+        assert_eq!(Mode::Fuzzel.format(&item()), "Firefox Web Browser\0icon\x1ffirefox");
+        assert_eq!(Mode::Wofi.format(&item()), "img:firefox:text:Firefox Web Browser");
+        assert_eq!(Mode::Wofi.format(&plain), "Vim");
+        assert_eq!(Mode::Dmenu.format(&item()), "Firefox Web Browser");
+    }
+
+    #[test]
+    fn test_position() {
+        let items = vec![Item { text: "Vim".into(), ..Item::default() }, item()];
+
+        assert_eq!(Mode::Dmenu.position(&items, "Vim\n"), Some(0));
+        assert_eq!(Mode::Fuzzel.position(&items, "Firefox Web Browser"), Some(1));
+        assert_eq!(Mode::Wofi.position(&items, "img:/icons/firefox.png:text:Firefox Web Browser\n"), Some(1));
+        assert_eq!(Mode::Wofi.position(&items, "Vim"), Some(0));
+        assert_eq!(Mode::Rofi.position(&items, "Emacs"), None);
+    }
+}
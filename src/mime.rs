@@ -0,0 +1,614 @@
+//! Finding the MIME type of a file or URL with the shared-mime-info database
+//!
+//! The type of a file is guessed from its name with the globs in `mime/globs2` of each data
+//! directory. When no glob matches, or globs of the same weight disagree, the first bytes of
+//! the file are compared against the rules in `mime/magic`, and failing those, a file that
+//! looks like text is `text/plain`. URLs are opened by the handler of their scheme, as
+//! `x-scheme-handler/SCHEME`, except `file://` URLs, which are typed like the file they name.
+//!
+//! The database also knows other names for types, in `mime/aliases`, and the types each type is
+//! a kind of, in `mime/subclasses`; every `text/*` type is also a kind of `text/plain`. So an
+//! application for `text/plain` opens `text/x-csrc`, and one for `application/x-pdf` opens
+//! `application/pdf`.
+
+use std::cmp::Reverse;
+use std::fs::{self, File};
+use std::io::Read;
+use std::iter;
+use std::path::{Path, PathBuf};
+
+use crate::exec::Target;
+
+/// The type of data that's nothing more specific
+pub const DEFAULT_TYPE: &str = "application/octet-stream";
+
+pub const TEXT_TYPE: &str = "text/plain";
+
+pub const DIRECTORY_TYPE: &str = "inode/directory";
+
+const MAGIC_HEADER: &[u8] = b"MIME-Magic\0\n";
+
+/// The most of a file read to compare against magic rules
+const MAX_MAGIC_EXTENT: usize = 1 << 20;
+
+/// The most of a file read to decide whether it's text
+const TEXT_SNIFF_LENGTH: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Glob {
+    weight: u32,
+    mime: String,
+    pattern: String,
+    case_sensitive: bool,
+}
+
+impl Glob {
+    fn is_literal(&self) -> bool {
+        !self.pattern.contains(&['*', '?', '['][..])
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        if self.case_sensitive {
+            fnmatch(&self.pattern.chars().collect::<Vec<char>>(), &name.chars().collect::<Vec<char>>())
+        } else {
+            fnmatch(&self.pattern.to_lowercase().chars().collect::<Vec<char>>(),
+                &name.to_lowercase().chars().collect::<Vec<char>>())
+        }
+    }
+}
+
+/// Match a name against a shell glob with `*`, `?` and `[...]`
+fn fnmatch(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|idx| fnmatch(rest, &name[idx..])),
+        Some(('?', rest)) => !name.is_empty() && fnmatch(rest, &name[1..]),
+        Some(('[', rest)) => {
+            let close = match rest.iter().skip(1).position(|c| *c == ']') {
+                Some(idx) => idx + 1,
+                // An unterminated class is matched literally
+                None => return name.first() == Some(&'[') && fnmatch(rest, &name[1..]),
+            };
+
+            let (negated, class) = match rest[..close].split_first() {
+                Some(('!', class)) => (true, class),
+                _ => (false, &rest[..close]),
+            };
+
+            let c = match name.first() {
+                Some(c) => *c,
+                None => return false,
+            };
+
+            let mut found = false;
+            let mut idx = 0;
+            while idx < class.len() {
+                if class.get(idx + 1) == Some(&'-') && idx + 2 < class.len() {
+                    found |= class[idx] <= c && c <= class[idx + 2];
+                    idx += 3;
+                } else {
+                    found |= class[idx] == c;
+                    idx += 1;
+                }
+            }
+
+            found != negated && fnmatch(&rest[close + 1..], &name[1..])
+        },
+        Some((c, rest)) => name.first() == Some(c) && fnmatch(rest, &name[1..]),
+    }
+}
+
+/// One line of a magic section, which only counts when a rule of the indent before it matched
+#[derive(Debug, Clone, PartialEq)]
+struct MagicRule {
+    indent: u32,
+    offset: usize,
+    /// How many offsets from `offset` on the value may start at
+    range: usize,
+    value: Vec<u8>,
+    mask: Option<Vec<u8>>,
+}
+
+impl MagicRule {
+    fn matches(&self, data: &[u8]) -> bool {
+        (self.offset..self.offset + self.range).any(|start| {
+            let window = match data.get(start..start + self.value.len()) {
+                Some(window) => window,
+                None => return false,
+            };
+
+            match &self.mask {
+                Some(mask) => window.iter().zip(&self.value).zip(mask)
+                    .all(|((byte, value), mask)| byte & mask == value & mask),
+                None => window == &self.value[..],
+            }
+        })
+    }
+
+    fn extent(&self) -> usize {
+        self.offset + self.range + self.value.len()
+    }
+}
+
+/// Whether any rule of a level matches, along with one of the rules nested under it if it has any
+fn rules_match(rules: &[MagicRule], data: &[u8]) -> bool {
+    let mut idx = 0;
+    while idx < rules.len() {
+        let rule = &rules[idx];
+        let end = rules[idx + 1..].iter()
+            .position(|next| next.indent <= rule.indent)
+            .map_or(rules.len(), |len| idx + 1 + len);
+        let nested = &rules[idx + 1..end];
+
+        if rule.matches(data) && (nested.is_empty() || rules_match(nested, data)) {
+            return true;
+        }
+
+        idx = end;
+    }
+
+    false
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Magic {
+    priority: u32,
+    mime: String,
+    rules: Vec<MagicRule>,
+}
+
+/// Reads the binary `magic` file, where values are prefixed by their length
+struct MagicReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> MagicReader<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek().is_some_and(|b| b.is_ascii_digit()) {
+            self.pos += 1;
+        }
+
+        std::str::from_utf8(&self.data[start..self.pos]).ok()?.parse().ok()
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(b) = self.peek() {
+            self.pos += 1;
+            if b == b'\n' {
+                break;
+            }
+        }
+    }
+
+    /// Read a `[priority:type]` heading
+    fn heading(&mut self) -> Option<(u32, String)> {
+        let end = self.pos + self.data[self.pos..].iter().position(|b| *b == b'\n')?;
+        let heading = std::str::from_utf8(&self.data[self.pos + 1..end]).ok()?.strip_suffix(']')?;
+        self.pos = end + 1;
+
+        let idx = heading.find(':')?;
+        Some((heading[..idx].parse().ok()?, heading[idx + 1..].into()))
+    }
+
+    /// Read a `[indent]>offset=value[&mask][~word-size][+range]` line
+    fn rule(&mut self) -> Option<MagicRule> {
+        let indent = if self.peek() == Some(b'>') { 0 } else { self.number()? as u32 };
+        if self.take(1)? != b">" {
+            return None;
+        }
+
+        let offset = self.number()?;
+        if self.take(1)? != b"=" {
+            return None;
+        }
+
+        let len = self.take(2)?;
+        let len = usize::from(len[0]) << 8 | usize::from(len[1]);
+        let mut value = self.take(len)?.to_vec();
+
+        let mut mask = None;
+        let mut word_size = 1;
+        let mut range = 1;
+        loop {
+            match self.take(1)? {
+                b"&" => mask = Some(self.take(len)?.to_vec()),
+                b"~" => word_size = self.number()?,
+                b"+" => range = self.number()?,
+                b"\n" => break,
+                _ => return None,
+            }
+        }
+
+        // Values of wider words are written big-endian, but compared in the host's order
+        if word_size > 1 && cfg!(target_endian = "little") {
+            for bytes in Some(&mut value).into_iter().chain(mask.as_mut()) {
+                bytes.chunks_mut(word_size).for_each(|word| word.reverse());
+            }
+        }
+
+        Some(MagicRule { indent, offset, range: range.max(1), value, mask })
+    }
+}
+
+/// The globs and magic of the shared-mime-info database
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MimeDatabase {
+    globs: Vec<Glob>,
+    magic: Vec<Magic>,
+    /// Other names for types, each with the name it stands for
+    aliases: Vec<(String, String)>,
+    /// Types with a type they're a kind of
+    subclasses: Vec<(String, String)>,
+    /// Types whose globs were replaced with `__NOGLOBS__` by a `globs2` file already added
+    no_globs: Vec<String>,
+}
+
+impl MimeDatabase {
+    /// Load the database from the `mime` directory of every data directory
+    pub fn load() -> MimeDatabase {
+        let dirs = crate::data_dirs().into_iter()
+            .map(|dir| dir.join("mime"))
+            .collect::<Vec<PathBuf>>();
+
+        MimeDatabase::load_dirs(&dirs)
+    }
+
+    /// Load the database from `mime` directories, most important first
+    pub fn load_dirs(dirs: &[PathBuf]) -> MimeDatabase {
+        let mut db = MimeDatabase::default();
+        for dir in dirs {
+            if let Ok(globs) = fs::read_to_string(dir.join("globs2")) {
+                db.add_globs(&globs);
+            }
+
+            if let Ok(magic) = fs::read(dir.join("magic")) {
+                db.add_magic(&magic);
+            }
+
+            if let Ok(aliases) = fs::read_to_string(dir.join("aliases")) {
+                db.add_aliases(&aliases);
+            }
+
+            if let Ok(subclasses) = fs::read_to_string(dir.join("subclasses")) {
+                db.add_subclasses(&subclasses);
+            }
+        }
+
+        db
+    }
+
+    /// Add the lines of a `globs2` file, which are `weight:type:glob[:flags]`. Files are added
+    /// most important first, and a type given the glob `__NOGLOBS__` keeps only the globs of that
+    /// file, dropping those of the less important files added after it.
+    pub fn add_globs(&mut self, contents: &str) {
+        let mut no_globs = Vec::new();
+        for line in contents.lines().filter(|line| !line.starts_with('#')) {
+            let mut fields = line.splitn(4, ':');
+            let (weight, mime, pattern) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(weight), Some(mime), Some(pattern)) => (weight, mime, pattern),
+                _ => continue,
+            };
+
+            if pattern == "__NOGLOBS__" {
+                no_globs.push(mime.to_string());
+                continue;
+            }
+
+            let weight = match weight.parse() {
+                Ok(weight) if !self.no_globs.iter().any(|no_globs| no_globs == mime) => weight,
+                _ => continue,
+            };
+
+            let case_sensitive = fields.next()
+                .is_some_and(|flags| flags.split(',').any(|flag| flag == "cs"));
+
+            self.globs.push(Glob { weight, mime: mime.into(), pattern: pattern.into(), case_sensitive });
+        }
+
+        self.no_globs.extend(no_globs);
+    }
+
+    /// Add the sections of a `magic` file, stopping at the first one that can't be read
+    pub fn add_magic(&mut self, data: &[u8]) {
+        let mut reader = match data.strip_prefix(MAGIC_HEADER) {
+            Some(data) => MagicReader { data, pos: 0 },
+            None => return,
+        };
+
+        while reader.peek() == Some(b'[') {
+            let (priority, mime) = match reader.heading() {
+                Some(heading) => heading,
+                None => return,
+            };
+
+            let mut rules = Vec::new();
+            while reader.peek().is_some_and(|b| b != b'[') {
+                let start = reader.pos;
+                match reader.rule() {
+                    Some(rule) => rules.push(rule),
+                    // Lines that aren't understood are skipped, as the spec asks
+                    None => {
+                        reader.pos = start;
+                        reader.skip_line();
+                    },
+                }
+            }
+
+            self.magic.push(Magic { priority, mime, rules });
+        }
+
+        self.magic.sort_by_key(|magic| Reverse(magic.priority));
+    }
+
+    /// The types whose globs best match a file name. Literal names beat patterns, then higher
+    /// weights, then case-sensitive globs, then longer patterns; several types are returned
+    /// when the best ones tie.
+    pub fn types_for_name(&self, name: &str) -> Vec<&str> {
+        let rank = |glob: &Glob| (glob.is_literal(), glob.weight, glob.case_sensitive, glob.pattern.len());
+        let matches = self.globs.iter()
+            .filter(|glob| glob.matches(name))
+            .collect::<Vec<&Glob>>();
+
+        let best = match matches.iter().map(|glob| rank(glob)).max() {
+            Some(best) => best,
+            None => return vec![],
+        };
+
+        let mut types: Vec<&str> = Vec::new();
+        for glob in matches.into_iter().filter(|glob| rank(glob) == best) {
+            if !types.contains(&glob.mime.as_str()) {
+                types.push(&glob.mime);
+            }
+        }
+
+        types
+    }
+
+    /// The type of the first magic section, by priority, that matches some data
+    pub fn type_for_data(&self, data: &[u8]) -> Option<&str> {
+        self.magic.iter()
+            .find(|magic| rules_match(&magic.rules, data))
+            .map(|magic| magic.mime.as_str())
+    }
+
+    fn magic_extent(&self) -> usize {
+        self.magic.iter()
+            .flat_map(|magic| &magic.rules)
+            .map(MagicRule::extent)
+            .max()
+            .unwrap_or(0)
+            .clamp(TEXT_SNIFF_LENGTH, MAX_MAGIC_EXTENT)
+    }
+
+    /// The type of a file, from its name and then its contents
+    pub fn type_for_path(&self, path: &Path) -> String {
+        if path.is_dir() {
+            return DIRECTORY_TYPE.into();
+        }
+
+        let name = path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+        let types = self.types_for_name(&name);
+        if let [mime] = types.as_slice() {
+            return mime.to_string();
+        }
+
+        let mut data = Vec::new();
+        if let Ok(file) = File::open(path) {
+            let _ = file.take(self.magic_extent() as u64).read_to_end(&mut data);
+        }
+
+        if let Some(mime) = self.type_for_data(&data) {
+            return mime.into();
+        }
+
+        match types.first() {
+            Some(mime) => mime.to_string(),
+            None if looks_like_text(&data) => TEXT_TYPE.into(),
+            None => DEFAULT_TYPE.into(),
+        }
+    }
+
+    /// Add the lines of an `aliases` file, which are `alias type`
+    pub fn add_aliases(&mut self, contents: &str) {
+        self.aliases.extend(pairs(contents));
+    }
+
+    /// Add the lines of a `subclasses` file, which are `type parent`
+    pub fn add_subclasses(&mut self, contents: &str) {
+        self.subclasses.extend(pairs(contents));
+    }
+
+    /// The name a type is an alias for, or the type itself
+    pub fn unalias(&self, mime: &str) -> String {
+        self.aliases.iter()
+            .find(|(alias, _)| alias.eq_ignore_ascii_case(mime))
+            .map_or_else(|| mime.to_lowercase(), |(_, mime)| mime.clone())
+    }
+
+    /// A type with every alias of it
+    fn names(&self, mime: &str) -> Vec<String> {
+        iter::once(mime.to_string())
+            .chain(self.aliases.iter()
+                .filter(|(_, canonical)| canonical.eq_ignore_ascii_case(mime))
+                .map(|(alias, _)| alias.clone()))
+            .collect()
+    }
+
+    /// The types a type is directly a kind of
+    pub fn parents(&self, mime: &str) -> Vec<String> {
+        let mime = self.unalias(mime);
+        let mut parents = self.subclasses.iter()
+            .filter(|(child, _)| child.eq_ignore_ascii_case(&mime))
+            .map(|(_, parent)| self.unalias(parent))
+            .collect::<Vec<String>>();
+
+        if mime.starts_with("text/") && mime != TEXT_TYPE && !parents.iter().any(|parent| parent == TEXT_TYPE) {
+            parents.push(TEXT_TYPE.into());
+        }
+
+        parents
+    }
+
+    /// A type and the types it's a kind of, nearest first, each with its aliases. This is the
+    /// order applications are looked for in, so that those for a type come before those for
+    /// its parents.
+    pub fn lineage(&self, mime: &str) -> Vec<Vec<String>> {
+        let mut types = vec![self.unalias(mime)];
+        let mut idx = 0;
+        while idx < types.len() {
+            for parent in self.parents(&types[idx]) {
+                if !types.contains(&parent) {
+                    types.push(parent);
+                }
+            }
+
+            idx += 1;
+        }
+
+        let mut lineage = types.iter()
+            .map(|mime| self.names(mime))
+            .collect::<Vec<Vec<String>>>();
+
+        // An alias that was asked for is kept, even if the database doesn't know it
+        if !lineage[0].iter().any(|name| name.eq_ignore_ascii_case(mime)) {
+            lineage[0].push(mime.into());
+        }
+
+        lineage
+    }
+
+    /// The type of a file or URL, where URLs other than `file://` are typed by their scheme
+    pub fn type_for_target(&self, target: &Target) -> String {
+        if let Some(path) = target.to_path() {
+            return self.type_for_path(&path);
+        }
+
+        let url = target.to_url();
+        let scheme = url.split(':').next().unwrap_or_default();
+        format!("x-scheme-handler/{}", scheme.to_lowercase())
+    }
+}
+
+/// The lines of a file of whitespace-separated pairs of types
+fn pairs(contents: &str) -> Vec<(String, String)> {
+    contents.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            Some((words.next()?.to_lowercase(), words.next()?.to_lowercase()))
+        })
+        .collect()
+}
+
+/// Whether data is UTF-8 without control characters other than whitespace. A character cut off
+/// at the end of the data doesn't count against it.
+fn looks_like_text(data: &[u8]) -> bool {
+    let data = &data[..data.len().min(TEXT_SNIFF_LENGTH)];
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(err) if err.error_len().is_none() => std::str::from_utf8(&data[..err.valid_up_to()]).unwrap_or_default(),
+        Err(..) => return false,
+    };
+
+    !text.chars().any(|c| c.is_control() && !c.is_whitespace())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_globs() {
+        let mut db = MimeDatabase::default();
+        db.add_globs("# comment\n\
+            50:text/x-csrc:*.c:cs\n\
+            50:text/x-csrc:*.c\n\
+            50:text/x-c++src:*.C:cs\n\
+            50:text/x-c++src:*.C\n\
+            50:application/gzip:*.gz\n\
+            50:application/x-compressed-tar:*.tar.gz\n\
+            50:text/x-makefile:makefile\n\
+            10:text/x-makefile:*.mk\n\
+            50:text/x-readme:README*\n\
+            50:image/x-foo:img[0-9].?\n\
+            50:application/x-a:*.dup\n\
+            50:application/x-b:*.dup\n\
+            50:text/x-ignored:__NOGLOBS__\n");
+
+        assert_eq!(db.types_for_name("main.c"), vec!["text/x-csrc"]);
+        assert_eq!(db.types_for_name("main.C"), vec!["text/x-c++src"]);
+        assert_eq!(db.types_for_name("src.TAR.GZ"), vec!["application/x-compressed-tar"]);
+        assert_eq!(db.types_for_name("Makefile"), vec!["text/x-makefile"]);
+        assert_eq!(db.types_for_name("README.md"), vec!["text/x-readme"]);
+        assert_eq!(db.types_for_name("img4.x"), vec!["image/x-foo"]);
+        assert!(db.types_for_name("imga.x").is_empty());
+        assert_eq!(db.types_for_name("a.dup"), vec!["application/x-a", "application/x-b"]);
+        assert!(db.types_for_name("__NOGLOBS__").is_empty());
+
+        // The globs of less important files are dropped, but not those next to `__NOGLOBS__`
+        let mut db = MimeDatabase::default();
+        db.add_globs("50:text/x-makefile:__NOGLOBS__\n50:text/x-makefile:GNUmakefile\n");
+        db.add_globs("50:text/x-makefile:*.mk\n50:text/x-csrc:*.c\n");
+        assert!(db.types_for_name("rules.mk").is_empty());
+        assert_eq!(db.types_for_name("GNUmakefile"), vec!["text/x-makefile"]);
+        assert_eq!(db.types_for_name("main.c"), vec!["text/x-csrc"]);
+    }
+
+    #[test]
+    fn test_magic() {
+        let mut magic = MAGIC_HEADER.to_vec();
+        magic.extend_from_slice(b"[50:image/png]\n>0=\x00\x04\x89PNG\n");
+        magic.extend_from_slice(b"[80:application/pdf]\n>0=\x00\x05%PDF-\n");
+        magic.extend_from_slice(b"[40:text/html]\n>0=\x00\x05<html+64\n");
+        // A tar file has `ustar` at 257, and this one also wants `x` at 0 under it, masked
+        magic.extend_from_slice(b"[60:application/x-test]\n>257=\x00\x05ustar\n1>0=\x00\x01X&\xdf\n");
+        magic.extend_from_slice(b"[20:application/x-broken]\n>0=?? weird\n>0=\x00\x02BR\n");
+
+        let mut db = MimeDatabase::default();
+        db.add_magic(&magic);
+
+        let mut tar = vec![b'x'; 300];
+        tar[257..262].copy_from_slice(b"ustar");
+
+        assert_eq!(db.type_for_data(b"\x89PNG\r\n"), Some("image/png"));
+        assert_eq!(db.type_for_data(b"%PDF-1.7"), Some("application/pdf"));
+        assert_eq!(db.type_for_data(b"\n\n  <html>"), Some("text/html"));
+        assert_eq!(db.type_for_data(&tar), Some("application/x-test"));
+        assert_eq!(db.type_for_data(&tar[1..]), None);
+        assert_eq!(db.type_for_data(b"BR"), Some("application/x-broken"));
+
+        assert!(looks_like_text("plain text\twith ünïcode\n".as_bytes()));
+        assert!(!looks_like_text(b"\x7fELF\x02\x01\x00"));
+    }
+
+    #[test]
+    fn test_lineage() {
+        let mut db = MimeDatabase::default();
+        db.add_aliases("application/x-pdf application/pdf\napplication/acrobat application/pdf\ntext/x-c text/x-csrc\n");
+        db.add_subclasses("text/x-c++src text/x-csrc\napplication/x-compressed-tar application/gzip\n");
+
+        assert_eq!(db.unalias("Application/X-PDF"), "application/pdf");
+        assert_eq!(db.parents("text/x-c++src"), vec!["text/x-csrc", "text/plain"]);
+        assert!(db.parents("text/plain").is_empty());
+
+        assert_eq!(db.lineage("application/x-pdf"), vec![vec!["application/pdf", "application/x-pdf", "application/acrobat"]]);
+        assert_eq!(db.lineage("text/x-c++src"), vec![
+            vec!["text/x-c++src".to_string()],
+            vec!["text/x-csrc".to_string(), "text/x-c".to_string()],
+            vec!["text/plain".to_string()],
+        ]);
+        assert_eq!(db.lineage("application/x-compressed-tar"), vec![vec!["application/x-compressed-tar"], vec!["application/gzip"]]);
+    }
+}
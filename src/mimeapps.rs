@@ -0,0 +1,562 @@
+//! Which applications open a MIME type, following the MIME Applications Associations spec
+//!
+//! Applications declare the types they open with `MimeType=`, which `update-desktop-database`
+//! also gathers into a `mimeinfo.cache` in each applications directory. `mimeapps.list` files
+//! change that: `[Added Associations]` and `[Removed Associations]` add and hide applications for
+//! a type, and `[Default Applications]` picks the one to open it with. The files are read in
+//! order of precedence, from `$XDG_CONFIG_HOME`, then each of `$XDG_CONFIG_DIRS`, then each
+//! source directory, where a `$desktop-mimeapps.list` for each desktop in `$XDG_CURRENT_DESKTOP`
+//! comes before `mimeapps.list`.
+//!
+//! Applications for a type are followed by those for its aliases and then for the types it's a
+//! kind of, nearest first, as given by `MimeDatabase::lineage`.
+//!
+//! An association removed by one file hides the same association in files of lower precedence,
+//! and as declared by the applications of its own directory and those after it.
+//!
+//! `mime_cache` builds the `mimeinfo.cache` of a directory byte for byte as
+//! update-desktop-database would, but with applications sorted so that it doesn't depend on the
+//! order files are found in.
+//!
+//! Defaults are changed in `$XDG_CONFIG_HOME/mimeapps.list` with `MimeAppsList`, which edits the
+//! file line by line so that comments and everything else in it are kept.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::iter;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::slice;
+
+use color_eyre::Result;
+use eyre::WrapErr;
+
+use crate::entry::DesktopEntry;
+
+pub const DEFAULT_APPLICATIONS: &str = "Default Applications";
+pub const ADDED_ASSOCIATIONS: &str = "Added Associations";
+pub const REMOVED_ASSOCIATIONS: &str = "Removed Associations";
+
+/// The group of `mimeinfo.cache`
+pub const MIME_CACHE: &str = "MIME Cache";
+
+/// The associations of one directory
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Layer {
+    /// The mimeapps.list files of the directory, most important first
+    lists: Vec<DesktopEntry>,
+    /// The directory's `mimeinfo.cache`, for applications directories
+    cache: Option<DesktopEntry>,
+    /// Applications in the directory, each with the types it declares
+    apps: Vec<(String, Vec<String>)>,
+}
+
+impl Layer {
+    fn read(dir: &Path, desktops: &[String]) -> Layer {
+        let lists = desktops.iter()
+            .map(|desktop| format!("{}-mimeapps.list", desktop))
+            .chain(iter::once("mimeapps.list".to_string()))
+            .map(|name| dir.join(name))
+            .filter(|path| path.is_file())
+            .filter_map(|path| DesktopEntry::read(path).ok())
+            .collect();
+
+        Layer { lists, ..Layer::default() }
+    }
+
+    /// The applications of the directory that declare a type, from `mimeinfo.cache` first
+    fn declared(&self, mime: &str) -> Vec<String> {
+        let mut ids = self.cache.as_ref()
+            .and_then(|cache| cache.group(MIME_CACHE))
+            .and_then(|group| group.get_list(mime))
+            .unwrap_or_default();
+
+        for (id, types) in &self.apps {
+            if types.iter().any(|t| t.eq_ignore_ascii_case(mime)) && !ids.contains(id) {
+                ids.push(id.clone());
+            }
+        }
+
+        ids
+    }
+}
+
+/// The IDs listed for a type in a group of a mimeapps.list
+fn listed(list: &DesktopEntry, group: &str, mime: &str) -> Vec<String> {
+    list.group(group)
+        .and_then(|group| group.get_list(mime))
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|id| !id.is_empty())
+        .collect()
+}
+
+/// The desktops named by `$XDG_CURRENT_DESKTOP`, in lowercase as used in file names
+fn current_desktops() -> Vec<String> {
+    env::var("XDG_CURRENT_DESKTOP")
+        .map(|desktops| desktops.split(':')
+            .filter(|desktop| !desktop.is_empty())
+            .map(str::to_lowercase)
+            .collect())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MimeApps {
+    layers: Vec<Layer>,
+    /// Desktop file IDs of the installed applications, which are the only ones given out
+    installed: Vec<String>,
+}
+
+impl MimeApps {
+    /// Read the associations for the scanned entries of some source directories. Entries with
+    /// `Hidden=true` count as deleted.
+    pub fn load(sources: &[PathBuf], entries: &[DesktopEntry]) -> MimeApps {
+        let entries = entries.iter()
+            .filter(|entry| !entry.get_bool("Hidden").unwrap_or(false))
+            .collect::<Vec<&DesktopEntry>>();
+        let desktops = current_desktops();
+        let mut layers = crate::config_dirs().iter()
+            .map(|dir| Layer::read(dir, &desktops))
+            .collect::<Vec<Layer>>();
+
+        for source in sources {
+            let mut layer = Layer::read(source, &desktops);
+            layer.cache = Some(source.join("mimeinfo.cache"))
+                .filter(|path| path.is_file())
+                .and_then(|path| DesktopEntry::read(path).ok());
+            layer.apps = entries.iter()
+                .filter(|entry| crate::source_dir(&entry.path, sources) == Some(source.as_path()))
+                .map(|entry| (crate::desktop_id(&entry.path, sources), entry.get_list("MimeType").unwrap_or_default()))
+                .collect();
+
+            layers.push(layer);
+        }
+
+        let installed = entries.iter()
+            .map(|entry| crate::desktop_id(&entry.path, sources))
+            .collect();
+
+        MimeApps { layers, installed }
+    }
+
+    fn is_installed(&self, id: &str) -> bool {
+        self.installed.iter().any(|installed| installed == id)
+    }
+
+    /// The applications that open a type, most preferred first
+    pub fn handlers(&self, mime: &str) -> Vec<String> {
+        let mut handlers: Vec<String> = Vec::new();
+        let mut removed: Vec<String> = Vec::new();
+        let add = |handlers: &mut Vec<String>, removed: &[String], ids: Vec<String>| {
+            for id in ids {
+                if self.is_installed(&id) && !removed.contains(&id) && !handlers.contains(&id) {
+                    handlers.push(id);
+                }
+            }
+        };
+
+        for layer in &self.layers {
+            for list in &layer.lists {
+                add(&mut handlers, &removed, listed(list, ADDED_ASSOCIATIONS, mime));
+                removed.extend(listed(list, REMOVED_ASSOCIATIONS, mime));
+            }
+
+            add(&mut handlers, &removed, layer.declared(mime));
+        }
+
+        handlers
+    }
+
+    /// The applications that open any type of a lineage, those for a type before those for its
+    /// parents
+    pub fn handlers_for(&self, lineage: &[Vec<String>]) -> Vec<String> {
+        let mut handlers = Vec::new();
+        for mime in lineage.iter().flatten() {
+            for id in self.handlers(mime) {
+                if !handlers.contains(&id) {
+                    handlers.push(id);
+                }
+            }
+        }
+
+        handlers
+    }
+
+    /// The first installed application named as the default for a type
+    fn explicit_default(&self, mime: &str) -> Option<String> {
+        let mut removed: Vec<String> = Vec::new();
+
+        for list in self.layers.iter().flat_map(|layer| &layer.lists) {
+            let default = listed(list, DEFAULT_APPLICATIONS, mime).into_iter()
+                .find(|id| self.is_installed(id) && !removed.contains(id));
+
+            if default.is_some() {
+                return default;
+            }
+
+            removed.extend(listed(list, REMOVED_ASSOCIATIONS, mime));
+        }
+
+        None
+    }
+
+    /// The application that opens a type: the first installed one named as its default, or
+    /// else the most preferred one that opens it
+    pub fn default(&self, mime: &str) -> Option<String> {
+        self.explicit_default(mime)
+            .or_else(|| self.handlers(mime).into_iter().next())
+    }
+
+    /// The application that opens any type of a lineage. Like GIO, a default named for a parent
+    /// type wins over applications that only declare the type itself.
+    pub fn default_for(&self, lineage: &[Vec<String>]) -> Option<String> {
+        lineage.iter().flatten()
+            .find_map(|mime| self.explicit_default(mime))
+            .or_else(|| self.handlers_for(lineage).into_iter().next())
+    }
+}
+
+/// Whether a MIME type is `type/subtype`, both made of the characters RFC 2045 allows in a token
+pub fn is_valid_mime_type(mime: &str) -> bool {
+    let is_token = |part: &str| !part.is_empty()
+        && part.chars().all(|c| c.is_ascii_alphanumeric() || "!#$&-^_.+".contains(c));
+
+    match mime.find('/') {
+        Some(idx) => is_token(&mime[..idx]) && is_token(&mime[idx + 1..]),
+        None => false,
+    }
+}
+
+/// The `mimeinfo.cache` of an applications directory and its subdirectories. Files that can't be
+/// read and those with `Hidden=true` are left out, as are invalid types.
+pub fn mime_cache(dir: &Path) -> String {
+    let dir = dir.to_path_buf();
+    let apps = crate::list_desktop_files(&dir).into_iter()
+        .filter_map(|path| DesktopEntry::read(path).ok())
+        .filter(|entry| !entry.get_bool("Hidden").unwrap_or(false))
+        .map(|entry| (crate::desktop_id(&entry.path, slice::from_ref(&dir)), entry.get_list("MimeType").unwrap_or_default()))
+        .collect::<Vec<_>>();
+
+    mime_cache_contents(&apps)
+}
+
+/// Write a `mimeinfo.cache` for applications with the types they declare: each type in byte
+/// order, followed by the IDs of its applications in byte order, each ending with `;`
+pub fn mime_cache_contents(apps: &[(String, Vec<String>)]) -> String {
+    let mut types: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for (id, mimes) in apps {
+        for mime in mimes.iter().map(|mime| mime.trim()).filter(|mime| is_valid_mime_type(mime)) {
+            types.entry(mime).or_default().insert(id);
+        }
+    }
+
+    let mut contents = format!("[{}]\n", MIME_CACHE);
+    for (mime, ids) in types {
+        contents.push_str(mime);
+        contents.push('=');
+        for id in ids {
+            contents.push_str(id);
+            contents.push(';');
+        }
+
+        contents.push('\n');
+    }
+
+    contents
+}
+
+/// Write a file, replacing it only once it's written in full
+pub fn replace_file(path: &Path, contents: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .wrap_err_with(|| format!("failed to create {}", parent.display()))?;
+    }
+
+    let (tmp, mut out) = crate::cache::create_temp(path)
+        .wrap_err_with(|| format!("failed to create a temporary file for {}", path.display()))?;
+
+    let written = out.write_all(contents.as_bytes())
+        .wrap_err_with(|| format!("failed to write {}", tmp.display()))
+        .and_then(|_| fs::rename(&tmp, path)
+            .wrap_err_with(|| format!("failed to replace {}", path.display())));
+
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+
+    written
+}
+
+/// A mimeapps.list kept as its lines, so that changing one association leaves comments, other
+/// groups and other types as they were
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MimeAppsList {
+    lines: Vec<String>,
+}
+
+impl MimeAppsList {
+    /// Read a mimeapps.list, where a missing file is an empty one
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<MimeAppsList> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok(MimeAppsList::parse(&contents)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(MimeAppsList::default()),
+            Err(err) => Err(err).wrap_err_with(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    pub fn parse(contents: &str) -> MimeAppsList {
+        MimeAppsList { lines: contents.lines().map(String::from).collect() }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        replace_file(path.as_ref(), &self.contents())
+    }
+
+    pub fn contents(&self) -> String {
+        self.lines.iter()
+            .map(|line| format!("{}\n", line))
+            .collect()
+    }
+
+    /// The lines of a group, after its heading
+    fn group_lines(&self, group: &str) -> Option<Range<usize>> {
+        let heading = format!("[{}]", group);
+        let start = self.lines.iter().position(|line| line.trim() == heading)? + 1;
+        let end = self.lines[start..].iter()
+            .position(|line| line.trim_start().starts_with('['))
+            .map_or(self.lines.len(), |idx| start + idx);
+
+        Some(start..end)
+    }
+
+    fn find(&self, group: &str, key: &str) -> Option<usize> {
+        self.group_lines(group)?
+            .find(|idx| line_key(&self.lines[*idx]) == Some(key))
+    }
+
+    /// The IDs listed for a type in a group
+    pub fn get(&self, group: &str, mime: &str) -> Option<Vec<String>> {
+        let line = &self.lines[self.find(group, mime)?];
+        let value = &line[line.find('=')? + 1..];
+
+        Some(value.split(';')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(String::from)
+            .collect())
+    }
+
+    /// Set the value for a type in a group, replacing its line or else adding one at the end of
+    /// the group, which is added if it's missing
+    pub fn set(&mut self, group: &str, mime: &str, value: &str) {
+        let line = format!("{}={}", mime, value);
+        if let Some(idx) = self.find(group, mime) {
+            self.lines[idx] = line;
+            return;
+        }
+
+        match self.group_lines(group) {
+            Some(range) => {
+                // Blank lines before the next group stay before it
+                let idx = range.clone().rev()
+                    .find(|idx| !self.lines[*idx].trim().is_empty())
+                    .map_or(range.start, |idx| idx + 1);
+
+                self.lines.insert(idx, line);
+            },
+            None => {
+                if self.lines.last().is_some_and(|last| !last.trim().is_empty()) {
+                    self.lines.push(String::new());
+                }
+
+                self.lines.push(format!("[{}]", group));
+                self.lines.push(line);
+            },
+        }
+    }
+
+    /// Remove the line for a type from a group, returning whether there was one. A group left
+    /// with nothing but blank lines is removed as well.
+    pub fn remove(&mut self, group: &str, mime: &str) -> bool {
+        let idx = match self.find(group, mime) {
+            Some(idx) => idx,
+            None => return false,
+        };
+        self.lines.remove(idx);
+
+        if let Some(range) = self.group_lines(group) {
+            if self.lines[range.clone()].iter().all(|line| line.trim().is_empty()) {
+                self.lines.drain(range.start - 1..range.end);
+
+                while self.lines.last().is_some_and(|last| last.trim().is_empty()) {
+                    self.lines.pop();
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Make an application the default for a type. Like GIO, it's also put first among the
+    /// added associations, and no longer removed.
+    pub fn set_default(&mut self, mime: &str, id: &str) {
+        self.set(DEFAULT_APPLICATIONS, mime, id);
+
+        let added = iter::once(id.to_string())
+            .chain(self.get(ADDED_ASSOCIATIONS, mime).unwrap_or_default().into_iter().filter(|added| added != id))
+            .map(|id| id + ";")
+            .collect::<String>();
+        self.set(ADDED_ASSOCIATIONS, mime, &added);
+
+        if let Some(removed) = self.get(REMOVED_ASSOCIATIONS, mime).filter(|removed| removed.iter().any(|removed| removed == id)) {
+            let removed = removed.into_iter()
+                .filter(|removed| removed != id)
+                .map(|id| id + ";")
+                .collect::<String>();
+
+            if removed.is_empty() {
+                self.remove(REMOVED_ASSOCIATIONS, mime);
+            } else {
+                self.set(REMOVED_ASSOCIATIONS, mime, &removed);
+            }
+        }
+    }
+}
+
+/// The key of a `key=value` line, which comments and headings don't have
+fn line_key(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    if line.starts_with('#') || line.starts_with('[') {
+        return None;
+    }
+
+    line.find('=').map(|idx| line[..idx].trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(contents: &str) -> DesktopEntry {
+        DesktopEntry::parse("mimeapps.list", contents).unwrap()
+    }
+
+    fn mime_apps() -> MimeApps {
+        let user = Layer {
+            lists: vec![
+                list("[Default Applications]\ntext/plain=gone.desktop;nvim.desktop;\n\
+                    [Added Associations]\ntext/plain=nvim.desktop;\n\
+                    [Removed Associations]\ntext/plain=gedit.desktop;\n"),
+            ],
+            ..Layer::default()
+        };
+
+        let system = Layer {
+            lists: vec![list("[Default Applications]\ntext/plain=gedit.desktop\nimage/png=eog.desktop\n")],
+            cache: Some(DesktopEntry::parse("mimeinfo.cache", "[MIME Cache]\ntext/plain=gedit.desktop;kate.desktop;\n").unwrap()),
+            apps: vec![
+                ("vim.desktop".into(), vec!["text/plain".into(), "text/x-csrc".into()]),
+                ("kate.desktop".into(), vec!["text/plain".into()]),
+            ],
+        };
+
+        let installed = ["nvim.desktop", "gedit.desktop", "kate.desktop", "vim.desktop", "eog.desktop"];
+        MimeApps { layers: vec![user, system], installed: installed.iter().map(|id| id.to_string()).collect() }
+    }
+
+    #[test]
+    fn test_handlers() {
+        let apps = mime_apps();
+
+        assert_eq!(apps.handlers("text/plain"), vec!["nvim.desktop", "kate.desktop", "vim.desktop"]);
+        assert_eq!(apps.handlers("text/x-csrc"), vec!["vim.desktop"]);
+        assert!(apps.handlers("image/png").is_empty());
+    }
+
+    #[test]
+    fn test_default() {
+        let apps = mime_apps();
+
+        // gone.desktop is named first, but isn't installed
+        assert_eq!(apps.default("text/plain"), Some("nvim.desktop".into()));
+        assert_eq!(apps.default("image/png"), Some("eog.desktop".into()));
+        assert_eq!(apps.default("text/x-csrc"), Some("vim.desktop".into()));
+        assert_eq!(apps.default("video/mp4"), None);
+    }
+
+    #[test]
+    fn test_set_default() {
+        let mut list = MimeAppsList::parse("# Managed by hand\n\
+            [Default Applications]\n\
+            image/png=eog.desktop\n\
+            \n\
+            [Removed Associations]\n\
+            text/plain=vim.desktop;kate.desktop;\n");
+
+        list.set_default("text/plain", "vim.desktop");
+        list.set_default("image/png", "gimp.desktop");
+
+        assert_eq!(list.contents(), "# Managed by hand\n\
+            [Default Applications]\n\
+            image/png=gimp.desktop\n\
+            text/plain=vim.desktop\n\
+            \n\
+            [Removed Associations]\n\
+            text/plain=kate.desktop;\n\
+            \n\
+            [Added Associations]\n\
+            text/plain=vim.desktop;\n\
+            image/png=gimp.desktop;\n");
+        assert_eq!(list.get(ADDED_ASSOCIATIONS, "image/png"), Some(vec!["gimp.desktop".to_string()]));
+
+        assert!(list.remove(DEFAULT_APPLICATIONS, "text/plain"));
+        assert!(!list.remove(DEFAULT_APPLICATIONS, "text/plain"));
+        assert_eq!(list.get(DEFAULT_APPLICATIONS, "text/plain"), None);
+
+        assert!(list.remove(DEFAULT_APPLICATIONS, "image/png"));
+        assert!(list.remove(ADDED_ASSOCIATIONS, "text/plain"));
+        assert!(list.remove(ADDED_ASSOCIATIONS, "image/png"));
+        assert_eq!(list.contents(), "# Managed by hand\n\
+            [Removed Associations]\n\
+            text/plain=kate.desktop;\n");
+    }
+
+    #[test]
+    fn test_mime_cache() {
+        let apps = vec![
+            ("vim.desktop".to_string(), vec!["text/plain".to_string(), "text/x-csrc".to_string()]),
+            ("kde-kate.desktop".to_string(), vec!["text/plain".to_string(), "not a type".to_string(), "".to_string()]),
+            ("empty.desktop".to_string(), vec![]),
+            ("eog.desktop".to_string(), vec!["image/png".to_string(), "image/png".to_string()]),
+        ];
+
+        assert_eq!(mime_cache_contents(&apps), "[MIME Cache]\n\
+            image/png=eog.desktop;\n\
+            text/plain=kde-kate.desktop;vim.desktop;\n\
+            text/x-csrc=vim.desktop;\n");
+        assert_eq!(mime_cache_contents(&[]), "[MIME Cache]\n");
+
+        assert!(is_valid_mime_type("application/vnd.oasis.opendocument.text"));
+        assert!(is_valid_mime_type("x-scheme-handler/https"));
+        assert!(!is_valid_mime_type("text/"));
+        assert!(!is_valid_mime_type("text/plain/extra"));
+    }
+
+    #[test]
+    fn test_lineage() {
+        let apps = mime_apps();
+        let lineage = vec![
+            vec!["text/x-c++src".to_string()],
+            vec!["text/x-csrc".to_string(), "text/x-c".to_string()],
+            vec!["text/plain".to_string()],
+        ];
+
+        assert_eq!(apps.handlers_for(&lineage), vec!["vim.desktop", "nvim.desktop", "kate.desktop"]);
+        assert_eq!(apps.default_for(&lineage), Some("nvim.desktop".into()));
+        assert_eq!(apps.default_for(&lineage[1..2]), Some("vim.desktop".into()));
+    }
+}
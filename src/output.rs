@@ -0,0 +1,320 @@
+//! Rendering rows of column values for the terminal or other programs
+//!
+//! Comma output is RFC 4180 CSV. Tab and space output can't represent a tab or newline inside a
+//! value, so those are written as backslash escapes (`\t`, `\n`, `\r`, and `\\` for a literal
+//! backslash), which keeps one entry per line for tools like fzf.
+//!
+//! JSON output keeps the type of each field, so lists become arrays and booleans stay booleans.
+
+use std::borrow::Cow;
+use std::io::{self, Write};
+
+use serde_json::{Map, Value};
+use unicode_width::UnicodeWidthStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Separator {
+    Comma,
+    Tab,
+    Spaces,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+    JsonLines,
+}
+
+/// The value of one column for one entry
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    /// The entry has no value for the column
+    Empty,
+    Text(String),
+    List(Vec<String>),
+    Bool(bool),
+    Number(u64),
+    /// Every variant of a localized key, with `None` for the unlocalized value
+    Localized(Vec<(Option<String>, String)>),
+}
+
+impl Field {
+    /// Whether the field has a value worth showing: not missing, empty or `false`
+    pub fn is_present(&self) -> bool {
+        match self {
+            Field::Empty => false,
+            Field::Bool(b) => *b,
+            Field::List(items) => !items.is_empty(),
+            field => !field.to_text().is_empty(),
+        }
+    }
+
+    /// Render the field for delimited output. Lists keep the `;` separator used in .desktop
+    /// files and localized fields show their unlocalized value.
+    pub fn to_text(&self) -> String {
+        match self {
+            Field::Empty => String::new(),
+            Field::Text(s) => s.clone(),
+            Field::List(items) => items.join(";"),
+            Field::Bool(b) => b.to_string(),
+            Field::Number(n) => n.to_string(),
+            Field::Localized(values) => values.iter()
+                .find(|(locale, _)| locale.is_none())
+                .map_or(String::new(), |(_, value)| value.clone()),
+        }
+    }
+
+    /// Render the field as JSON. Localized fields become an object keyed by locale, with the
+    /// unlocalized value under `default`.
+    pub fn to_json(&self) -> Value {
+        match self {
+            Field::Empty => Value::Null,
+            Field::Text(s) => Value::String(s.clone()),
+            Field::List(items) => Value::Array(items.iter().cloned().map(Value::String).collect()),
+            Field::Bool(b) => Value::Bool(*b),
+            Field::Number(n) => Value::from(*n),
+            Field::Localized(values) => Value::Object(values.iter()
+                .map(|(locale, value)| {
+                    let key = locale.as_deref().unwrap_or("default").to_string();
+                    (key, Value::String(value.clone()))
+                })
+                .collect()),
+        }
+    }
+}
+
+/// Gap between padded columns when separating with spaces
+const COLUMN_GAP: usize = 2;
+
+/// Write rows of values, ending each row with a NUL instead of a line break when `null` is set
+pub fn write_rows<W: Write>(out: &mut W, rows: &[Vec<String>], separator: Separator, quote: bool, null: bool) -> io::Result<()> {
+    let rows = rows.iter()
+        .map(|row| row.iter()
+            .map(|value| format_value(value, separator, quote))
+            .collect::<Vec<Cow<str>>>())
+        .collect::<Vec<_>>();
+
+    write_formatted(out, &rows, separator, null)
+}
+
+/// Write rows like `write_rows`, each starting with a key that a program handed back the line
+/// reads unchanged. Only CSV quotes the key, since an unquoted one could break the row.
+pub fn write_keyed_rows<W: Write>(out: &mut W, keys: &[String], rows: &[Vec<String>], separator: Separator, quote: bool, null: bool) -> io::Result<()> {
+    let format_key = |key| match separator {
+        Separator::Comma => format_value(key, separator, quote),
+        Separator::Tab | Separator::Spaces => Cow::Borrowed(key),
+    };
+
+    let rows = keys.iter().zip(rows)
+        .map(|(key, row)| std::iter::once(format_key(key))
+            .chain(row.iter().map(|value| format_value(value, separator, quote)))
+            .collect::<Vec<Cow<str>>>())
+        .collect::<Vec<_>>();
+
+    write_formatted(out, &rows, separator, null)
+}
+
+fn write_formatted<W: Write>(out: &mut W, rows: &[Vec<Cow<str>>], separator: Separator, null: bool) -> io::Result<()> {
+    let terminator = |line_break| if null { "\0" } else { line_break };
+    match separator {
+        Separator::Comma => write_delimited(out, rows, ",", terminator("\r\n")),
+        Separator::Tab => write_delimited(out, rows, "\t", terminator("\n")),
+        Separator::Spaces => write_padded(out, rows, terminator("\n")),
+    }
+}
+
+fn write_delimited<W: Write>(out: &mut W, rows: &[Vec<Cow<str>>], delimiter: &str, terminator: &str) -> io::Result<()> {
+    for row in rows {
+        write!(out, "{}{}", row.join(delimiter), terminator)?;
+    }
+
+    Ok(())
+}
+
+fn write_padded<W: Write>(out: &mut W, rows: &[Vec<Cow<str>>], terminator: &str) -> io::Result<()> {
+    let mut widths: Vec<usize> = Vec::new();
+    for row in rows {
+        for (idx, value) in row.iter().enumerate() {
+            let width = value.width();
+            match widths.get_mut(idx) {
+                Some(max) if *max < width => *max = width,
+                Some(..) => (),
+                None => widths.push(width),
+            }
+        }
+    }
+
+    for row in rows {
+        let mut line = String::new();
+        for (idx, value) in row.iter().enumerate() {
+            line.push_str(value);
+
+            if idx + 1 < row.len() {
+                let pad = widths[idx] - value.width() + COLUMN_GAP;
+                line.push_str(&" ".repeat(pad));
+            }
+        }
+
+        write!(out, "{}{}", line.trim_end(), terminator)?;
+    }
+
+    Ok(())
+}
+
+/// Write records as a JSON array of objects, or one object per line for JSON Lines. Keys keep
+/// the order they have in each record.
+pub fn write_json<W: Write>(out: &mut W, records: &[Vec<(&str, Field)>], lines: bool) -> io::Result<()> {
+    let objects = records.iter()
+        .map(|record| Value::Object(record.iter()
+            .map(|(key, field)| (key.to_string(), field.to_json()))
+            .collect::<Map<String, Value>>()))
+        .collect::<Vec<Value>>();
+
+    if lines {
+        for object in objects {
+            serde_json::to_writer(&mut *out, &object)?;
+            writeln!(out)?;
+        }
+    } else {
+        serde_json::to_writer_pretty(&mut *out, &objects)?;
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+/// Escape and, if needed, quote a single value for the given separator
+pub fn format_value(value: &str, separator: Separator, quote: bool) -> Cow<'_, str> {
+    match separator {
+        Separator::Comma => {
+            let needs_quotes = quote || value.contains(&[',', '"', '\n', '\r'][..]);
+            if needs_quotes {
+                Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+            } else {
+                Cow::Borrowed(value)
+            }
+        },
+        Separator::Tab | Separator::Spaces => {
+            let needs_escape = value.contains(&['\\', '\t', '\n', '\r'][..]);
+            if !needs_escape && !quote {
+                return Cow::Borrowed(value);
+            }
+
+            let mut res = String::with_capacity(value.len() + 2);
+            if quote {
+                res.push('"');
+            }
+
+            for c in value.chars() {
+                match c {
+                    '\\' => res.push_str("\\\\"),
+                    '\t' => res.push_str("\\t"),
+                    '\n' => res.push_str("\\n"),
+                    '\r' => res.push_str("\\r"),
+                    '"' if quote => res.push_str("\\\""),
+                    c => res.push(c),
+                }
+            }
+
+            if quote {
+                res.push('"');
+            }
+
+            Cow::Owned(res)
+        },
+    }
+}
+
+/// Quote a string for a POSIX shell, leaving it alone if it has no special characters
+pub fn shell_quote(value: &str) -> Cow<'_, str> {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c);
+    if !value.is_empty() && value.chars().all(is_safe) {
+        return Cow::Borrowed(value);
+    }
+
+    Cow::Owned(format!("'{}'", value.replace('\'', r"'\''")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(rows: &[&[&str]], separator: Separator, quote: bool) -> String {
+        let rows = rows.iter()
+            .map(|row| row.iter().map(|v| v.to_string()).collect())
+            .collect::<Vec<Vec<String>>>();
+
+        let mut out = Vec::new();
+        write_rows(&mut out, &rows, separator, quote, false).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_csv() {
+        let rows: &[&[&str]] = &[&["Vim", "Edit \"text\", fast"], &["Two\nlines", ""]];
+
+        assert_eq!(render(rows, Separator::Comma, false), "Vim,\"Edit \"\"text\"\", fast\"\r\n\"Two\nlines\",\r\n");
+        assert_eq!(render(rows, Separator::Comma, true), "\"Vim\",\"Edit \"\"text\"\", fast\"\r\n\"Two\nlines\",\"\"\r\n");
+    }
+
+    #[test]
+    fn test_tab() {
+        let rows: &[&[&str]] = &[&["a\tb", "c\\d\ne"], &["\"q\"", "x"]];
+
+        assert_eq!(render(rows, Separator::Tab, false), "a\\tb\tc\\\\d\\ne\n\"q\"\tx\n");
+        assert_eq!(render(rows, Separator::Tab, true), "\"a\\tb\"\t\"c\\\\d\\ne\"\n\"\\\"q\\\"\"\t\"x\"\n");
+    }
+
+    #[test]
+    fn test_json() {
+        let records = vec![
+            vec![
+                ("name", Field::Localized(vec![(None, "Files".into()), (Some("de".into()), "Dateien".into())])),
+                ("categories", Field::List(vec!["GTK".into(), "Utility".into()])),
+                ("terminal", Field::Bool(false)),
+                ("icon", Field::Empty),
+            ],
+        ];
+
+        let mut out = Vec::new();
+        write_json(&mut out, &records, true).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+            "{\"name\":{\"default\":\"Files\",\"de\":\"Dateien\"},\"categories\":[\"GTK\",\"Utility\"],\"terminal\":false,\"icon\":null}\n");
+    }
+
+    #[test]
+    fn test_spaces() {
+        let rows: &[&[&str]] = &[&["Files", "File manager", "x"], &["Terminal", "", "y"], &["日本", "z", ""]];
+
+        assert_eq!(render(rows, Separator::Spaces, false), "Files     File manager  x\nTerminal                y\n日本      z\n");
+    }
+
+    #[test]
+    fn test_keyed() {
+        let keys = vec!["/apps/a b.desktop".to_string()];
+        let rows = vec![vec!["Vim".to_string()]];
+
+        let mut out = Vec::new();
+        write_keyed_rows(&mut out, &keys, &rows, Separator::Tab, true, false).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "/apps/a b.desktop\t\"Vim\"\n");
+
+        let keys = vec!["/apps/a,b.desktop".to_string()];
+        let mut out = Vec::new();
+        write_keyed_rows(&mut out, &keys, &rows, Separator::Comma, false, false).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "\"/apps/a,b.desktop\",Vim\r\n");
+    }
+
+    #[test]
+    fn test_null() {
+        let rows = vec![vec!["Vim".to_string(), "Two\nlines".to_string()], vec!["sh".to_string(), "".to_string()]];
+
+        let mut out = Vec::new();
+        write_rows(&mut out, &rows, Separator::Tab, false, true).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Vim\tTwo\\nlines\0sh\t\0");
+
+        let mut out = Vec::new();
+        write_rows(&mut out, &rows, Separator::Comma, false, true).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Vim,\"Two\nlines\"\0sh,\0");
+    }
+}
@@ -1,8 +1,18 @@
-use color_eyre::{Report, Result};
-use eyre::WrapErr;
 use thiserror::Error;
 
+use super::span::Position;
+
 #[derive(Error, Debug)]
 pub enum ParserError {
-    
-}
\ No newline at end of file
+    #[error("{0}: key `{1}` appears before any group heading")]
+    EntryOutsideSection(Position, String),
+
+    #[error("{0}: group heading is not terminated")]
+    UnterminatedHeading(Position),
+
+    #[error("{0}: expected `=` after key `{1}`")]
+    MissingEqual(Position, String),
+
+    #[error("{0}: unexpected {1}")]
+    UnexpectedToken(Position, String),
+}
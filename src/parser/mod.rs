@@ -3,50 +3,268 @@ pub mod span;
 pub mod tokens;
 pub mod tree;
 
-use color_eyre::{Report, Result};
+use color_eyre::Result;
 use std::iter::Peekable;
-use std::str::Chars;
 
-use tokens::Tokens;
+use error::ParserError;
+use span::{Position, Span};
+use tokens::{Token, TokenKind, Tokens};
+use tree::{Entry, Node, Section, Value, ValueKind, ValuePart};
 
 #[derive(PartialEq, Debug)]
 pub enum State {
-    ReadHeader,
-    ReadKey,
-    ReadValue,
-    ReadExec,
+    Header,
+    Key,
+    Value,
+    Exec,
 }
 
-pub struct Parser<'a> {
-    data: TokenData<'a>,
-    tokens: Option<tokens::Tokens>,
-    fwd: Vec<tokens::Token>,
+type TokenStream<'a> = Peekable<Tokens<'a>>;
+
+pub struct Parser {
+    data: String,
 }
 
 impl Parser {
     pub fn new(data: impl Into<String>) -> Parser {
-        Parser { data: TokenData::new(data) }
+        Parser { data: data.into() }
+    }
+
+    pub fn tokens(&self) -> Tokens<'_> {
+        Tokens::new(self.data.as_str())
+    }
+
+    pub fn parse(&self) -> Result<Vec<Section>> {
+        let mut tokens = self.tokens().peekable();
+        let mut sections: Vec<Section> = Vec::new();
+
+        while let Some(tok) = tokens.next() {
+            match &tok.kind {
+                TokenKind::LeftBracket => {
+                    let section = Parser::match_heading(tok, &mut tokens)?;
+                    sections.push(section);
+                },
+                TokenKind::Text(key) => {
+                    let key = key.trim().to_string();
+                    let section = match sections.last_mut() {
+                        Some(section) => section,
+                        None => return Err(ParserError::EntryOutsideSection(tok.span.start, key).into()),
+                    };
+
+                    let entry = Parser::match_entry(key, tok, &mut tokens)?;
+                    section.node.span.end = entry.node.span.end;
+                    section.entries.push(entry);
+                },
+                _ => return Err(ParserError::UnexpectedToken(tok.span.start, format!("`{:?}`", tok)).into()),
+            }
+        }
+
+        Ok(sections)
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Section>> {
+    fn match_heading(open: Token, tokens: &mut TokenStream) -> Result<Section> {
+        let row = open.span.start.row;
+        let mut node = Node::new(open.span);
+        node.push(open);
+
+        let heading = match Parser::next_on_row(tokens, row) {
+            Some(tok) => match &tok.kind {
+                TokenKind::Text(text) => {
+                    let heading = text.trim().to_string();
+                    node.push(tok);
+                    heading
+                },
+                _ => return Err(ParserError::UnexpectedToken(tok.span.start, format!("`{:?}` in group heading", tok)).into()),
+            },
+            None => return Err(ParserError::UnterminatedHeading(node.span.start).into()),
+        };
+
+        match Parser::next_on_row(tokens, row) {
+            Some(tok) if tok.is_right_bracket() => node.push(tok),
+            _ => return Err(ParserError::UnterminatedHeading(node.span.start).into()),
+        }
+
+        Ok(Section { node, heading, entries: Vec::new() })
+    }
+
+    fn match_entry(key: String, first: Token, tokens: &mut TokenStream) -> Result<Entry> {
+        let row = first.span.start.row;
+        let mut node = Node::new(first.span);
+        node.push(first);
+
+        let mut lang = None;
+        if let Some(open) = Parser::next_on_row_if(tokens, row, Token::is_left_bracket) {
+            node.push(open);
+
+            match Parser::next_on_row(tokens, row) {
+                Some(tok) => match &tok.kind {
+                    TokenKind::Text(text) => {
+                        lang = Some(text.trim().to_string());
+                        node.push(tok);
+                    },
+                    _ => return Err(ParserError::UnexpectedToken(tok.span.start, format!("`{:?}` in locale of `{}`", tok, key)).into()),
+                },
+                None => return Err(ParserError::MissingEqual(node.span.end, key).into()),
+            }
+
+            match Parser::next_on_row(tokens, row) {
+                Some(tok) if tok.is_right_bracket() => node.push(tok),
+                _ => return Err(ParserError::MissingEqual(node.span.end, key).into()),
+            }
+        }
+
+        match Parser::next_on_row(tokens, row) {
+            Some(tok) if tok.is_equal() => node.push(tok),
+            _ => return Err(ParserError::MissingEqual(node.span.end, key).into()),
+        }
+
+        let value = Parser::match_value(&key, row, node.span.end, tokens)?;
+        node.span.end = value.node.span.end;
+
+        Ok(Entry { node, key, lang, value })
+    }
+
+    fn match_value(key: &str, row: u32, start: Position, tokens: &mut TokenStream) -> Result<Value> {
+        let mut node = Node::new(Span::start(start));
+        let mut parts: Vec<ValuePart> = Vec::new();
+
+        while let Some(tok) = Parser::next_on_row(tokens, row) {
+            match &tok.kind {
+                TokenKind::Text(text) => Parser::push_literal(&mut parts, text),
+                TokenKind::Semicolon => Parser::push_literal(&mut parts, ";"),
+                TokenKind::Argument(c) => parts.push(ValuePart::Parameter(*c)),
+                _ => return Err(ParserError::UnexpectedToken(tok.span.start, format!("`{:?}` in value of `{}`", tok, key)).into()),
+            }
+
+            node.push(tok);
+        }
+
+        let kind = if key == "Exec" {
+            if let Some(ValuePart::Literal(first)) = parts.first_mut() {
+                *first = first.trim_start().to_string();
+            }
+
+            ValueKind::Exec(parts)
+        } else {
+            let text = parts.into_iter()
+                .filter_map(|part| match part {
+                    ValuePart::Literal(s) => Some(s),
+                    ValuePart::Parameter(..) => None,
+                })
+                .collect();
+
+            ValueKind::Simple(text)
+        };
 
+        Ok(Value { node, kind })
     }
 
-    fn match_heading(&mut self) -> Result<Section> {
+    fn push_literal(parts: &mut Vec<ValuePart>, text: &str) {
+        match parts.last_mut() {
+            Some(ValuePart::Literal(last)) => last.push_str(text),
+            _ => parts.push(ValuePart::Literal(text.into())),
+        }
+    }
 
+    fn next_on_row(tokens: &mut TokenStream, row: u32) -> Option<Token> {
+        Parser::next_on_row_if(tokens, row, |_| true)
     }
 
-    fn match_entry(&mut self) -> Result<Entry> {
-       self.data.match_token()
+    fn next_on_row_if<F>(tokens: &mut TokenStream, row: u32, pred: F) -> Option<Token>
+    where
+        F: Fn(&Token) -> bool
+    {
+        match tokens.peek() {
+            Some(tok) if tok.span.start.row == row && pred(tok) => tokens.next(),
+            _ => None,
+        }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let p = Parser::new(r#"
+        # leading comment
+        [Desktop Entry]
+        Name=Files
+        Name[de]=Dateien
+        Categories=GTK;Utility;
+        Exec=nautilus --new-window %U
+        Icon=
+
+        [Desktop Action new-window]
+        Name=New Window
+        "#);
+
+        let sections = p.parse().unwrap();
+        assert_eq!(sections.len(), 2);
+
+        let main = &sections[0];
+        assert_eq!(main.heading, "Desktop Entry");
+        assert_eq!(main.entries.len(), 5);
+
+        assert_eq!(main.entries[0].key, "Name");
+        assert_eq!(main.entries[0].lang, None);
+        assert_eq!(main.entries[0].value.raw(), "Files");
 
-    fn match_text(&mut self, tok tokens::Token) -> Option<tokens::Token> {
-        
+        assert_eq!(main.entries[1].key, "Name");
+        assert_eq!(main.entries[1].lang.as_deref(), Some("de"));
+        assert_eq!(main.entries[1].value.raw(), "Dateien");
+
+        assert_eq!(main.entries[2].value.raw(), "GTK;Utility;");
+
+        let exec = &main.entries[3].value;
+        assert_eq!(exec.raw(), "nautilus --new-window %U");
+        if let ValueKind::Exec(parts) = &exec.kind {
+            assert_eq!(parts.len(), 2);
+        } else {
+            panic!("expected an exec value");
+        }
+
+        assert_eq!(main.entries[4].key, "Icon");
+        assert_eq!(main.entries[4].value.raw(), "");
+
+        assert_eq!(sections[1].heading, "Desktop Action new-window");
+        assert_eq!(sections[1].entries[0].value.raw(), "New Window");
     }
 
-    fn next(&mut self) -> Option<tokens::Token> {
-        if len(self.fwd) == 0 {
-            
+    #[test]
+    fn test_parse_hash() {
+        let p = Parser::new("[Desktop Entry]\n\
+            # a comment\n\
+            \t# an indented comment\n\
+            Comment=Colour #1\n\
+            Exec=app --color=#fff %u\n");
+
+        let sections = p.parse().unwrap();
+        let entries = &sections[0].entries;
+        assert_eq!(entries.len(), 2);
+
+        assert_eq!(entries[0].value.raw(), "Colour #1");
+
+        let exec = &entries[1].value;
+        assert_eq!(exec.raw(), "app --color=#fff %u");
+        if let ValueKind::Exec(parts) = &exec.kind {
+            assert!(matches!(parts.as_slice(),
+                [ValuePart::Literal(text), ValuePart::Parameter('u')] if text == "app --color=#fff "));
+        } else {
+            panic!("expected an exec value");
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_errors() {
+        let p = Parser::new("Name=Orphan\n[Desktop Entry]\n");
+        assert!(p.parse().is_err());
+
+        let p = Parser::new("[Desktop Entry\nName=Files\n");
+        assert!(p.parse().is_err());
+
+        let p = Parser::new("[Desktop Entry]\nName\nExec=true\n");
+        assert!(p.parse().is_err());
+    }
+}
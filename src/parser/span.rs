@@ -1,7 +1,8 @@
 use std::cmp::{Ord, Ordering};
+use std::fmt;
 use std::ops::{Add, AddAssign, Sub, SubAssign};
 
-#[derive(PartialEq, Eq, PartialOrd, Clone, Copy, Debug)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub struct Position {
     pub row: u32,
     pub col: u32,
@@ -12,11 +13,23 @@ impl Ord for Position {
     fn cmp(&self, other: &Self) -> Ordering {
         match self.row.cmp(&other.row) {
             Ordering::Equal => self.col.cmp(&other.col),
-            c @ _ => c,
+            c => c,
         }
     }
 }
 
+impl PartialOrd for Position {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.row + 1, self.col + 1)
+    }
+}
+
 impl Add<u32> for Position {
     type Output = Self;
 
@@ -58,7 +71,7 @@ impl Position {
     }
 }
 
-#[derive(PartialEq, Eq, PartialOrd, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub struct Span {
     pub start: Position,
     pub end: Position,
@@ -66,7 +79,16 @@ pub struct Span {
 
 impl Ord for Span {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.cmp(&other)
+        match self.start.cmp(&other.start) {
+            Ordering::Equal => self.end.cmp(&other.end),
+            c => c,
+        }
+    }
+}
+
+impl PartialOrd for Span {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
@@ -122,12 +144,4 @@ impl Span {
     pub fn start(start: Position) -> Span {
         Span { start, end: start }
     }
-
-    pub fn finish(&mut self, end: Position) {
-        if self.start > end {
-            panic!("Invalid span: start and end are inverted");
-        }
-
-        self.end = end;
-    }
 }
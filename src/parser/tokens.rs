@@ -1,9 +1,9 @@
-use std::fmt;
+use std::fmt::{self, Debug, Write};
 use std::iter::Peekable;
 use std::str::Chars;
 
 use super::span::{Span, Position};
-use crate::parser::{Parser, State};
+use crate::parser::State;
 
 #[derive(Debug)]
 pub enum TokenKind {
@@ -22,16 +22,14 @@ pub struct Token {
 
 impl Debug for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        match self.kind {
-            TokenKind::Text(data) => f.write_fmt("\"{}\"", data),
+        match &self.kind {
+            TokenKind::Text(data) => write!(f, "\"{}\"", data),
             TokenKind::LeftBracket => f.write_char('['),
             TokenKind::RightBracket => f.write_char(']'),
             TokenKind::Equal => f.write_char('='),
             TokenKind::Semicolon => f.write_char(';'),
-            TokenKind::Argument(a) => f.write_fmt("%{}", a),
+            TokenKind::Argument(a) => write!(f, "%{}", a),
         }
-
-        Ok(())
     }
 }
 
@@ -47,46 +45,31 @@ impl Token {
         Token { kind, span }
     }
 
+    #[cfg(test)]
     pub fn is_text(&self) -> bool {
-        match self.kind {
-            TokenKind::Text(..) => true,
-            _ => false,
-        }
+        matches!(self.kind, TokenKind::Text(..))
     }
 
     pub fn is_left_bracket(&self) -> bool {
-        match self.kind {
-            TokenKind::LeftBracket => true,
-            _ => false,
-        }
+        matches!(self.kind, TokenKind::LeftBracket)
     }
 
     pub fn is_right_bracket(&self) -> bool {
-        match self.kind {
-            TokenKind::RightBracket => true,
-            _ => false,
-        }
+        matches!(self.kind, TokenKind::RightBracket)
     }
 
     pub fn is_equal(&self) -> bool {
-        match self.kind {
-            TokenKind::Equal => true,
-            _ => false,
-        }
+        matches!(self.kind, TokenKind::Equal)
     }
 
+    #[cfg(test)]
     pub fn is_semicolon(&self) -> bool {
-        match self.kind {
-            TokenKind::Semicolon => true,
-            _ => false,
-        }
+        matches!(self.kind, TokenKind::Semicolon)
     }
 
+    #[cfg(test)]
     pub fn is_argument(&self) -> bool {
-        match self.kind {
-            TokenKind::Argument(..) => true,
-            _ => false,
-        }
+        matches!(self.kind, TokenKind::Argument(..))
     }
 }
 
@@ -108,7 +91,7 @@ impl<'a> TokenData<'a> {
     }
 
     fn next(&mut self) -> Option<char> {
-        let c = if self.back_data.len() > 0 {
+        let c = if !self.back_data.is_empty() {
             self.back_data.pop()
         } else {
             self.data.next()
@@ -122,7 +105,7 @@ impl<'a> TokenData<'a> {
     }
 
     fn peek(&mut self) -> Option<&char> {
-        if self.back_data.len() > 0 {
+        if !self.back_data.is_empty() {
             return Some(&self.back_data[self.back_data.len() - 1]);
         }
 
@@ -139,6 +122,9 @@ pub struct Tokens<'a> {
     data: TokenData<'a>,
     state: State,
     buf: String,
+    /// Whether nothing but whitespace has been read on the current line, where a `#` starts a
+    /// comment. Anywhere else it's part of the text.
+    line_start: bool,
 }
 
 impl<'a> Tokens<'a> {
@@ -146,45 +132,52 @@ impl<'a> Tokens<'a> {
     {
         Tokens {
             data: TokenData::new(data),
-            state: State::ReadKey,
+            state: State::Key,
             buf: String::with_capacity(2048),
+            line_start: true,
         }
     }
 
     fn next_token(&mut self) -> Option<Token> {
+        let token = self.read_token();
+        self.line_start = false;
+        token
+    }
+
+    fn read_token(&mut self) -> Option<Token> {
         loop {
             match self.data.peek() {
-                Some(' ') | Some('\t') if self.state != State::ReadExec => self.skip_whitespace(),
-                Some('#') => self.skip_comment(),
+                Some(' ') | Some('\t') if self.state != State::Exec => self.skip_whitespace(),
+                Some('#') if self.line_start => self.skip_comment(),
                 Some('\n') | Some('\r') => self.advance_line(),
-                Some('[') if self.state == State::ReadKey => {
-                    self.state = State::ReadHeader;
+                Some('[') if self.state == State::Key => {
+                    self.state = State::Header;
 
                     self.data.next();
                     return Some(Token::new(TokenKind::LeftBracket, self.data.pos));
                 },
-                Some(']') if self.state == State::ReadHeader => {
-                    self.state = State::ReadKey;
+                Some(']') if self.state == State::Header => {
+                    self.state = State::Key;
 
                     self.data.next();
                     return Some(Token::new(TokenKind::RightBracket, self.data.pos));
                 },
-                Some('=') if self.state == State::ReadKey => {
-                    self.state = State::ReadValue;
+                Some('=') if self.state == State::Key => {
+                    self.state = State::Value;
                     self.state = if self.buf == "Exec" {
-                        State::ReadExec
+                        State::Exec
                     } else {
-                        State::ReadValue
+                        State::Value
                     };
 
                     self.data.next();
                     return Some(Token::new(TokenKind::Equal, self.data.pos));
                 },
-                Some(';') if self.state == State::ReadValue => {
+                Some(';') if self.state == State::Value => {
                     self.data.next();
                     return Some(Token::new(TokenKind::Semicolon, self.data.pos));
                 },
-                Some('%') if self.state == State::ReadExec => {
+                Some('%') if self.state == State::Exec => {
                     self.data.next();
 
                     if let Some(arg) = self.data.next() {
@@ -251,7 +244,8 @@ impl<'a> Tokens<'a> {
         }
 
         self.data.pos.newline();
-        self.state = State::ReadKey;
+        self.state = State::Key;
+        self.line_start = true;
     }
 
     fn read_text(&mut self) {
@@ -274,17 +268,17 @@ impl<'a> Tokens<'a> {
             }
 
             match self.data.peek() {
-                Some('[') if self.state == State::ReadKey => break,
-                Some(']') if self.state == State::ReadKey => break,
-                Some(']') if self.state == State::ReadHeader => break,
-                Some('=') if self.state == State::ReadKey => break,
-                Some(';') if self.state == State::ReadValue => break,
-                Some('\n') | Some('\r') | Some('#') => break,
-                Some('%') if self.state == State::ReadExec => {
+                Some('[') if self.state == State::Key => break,
+                Some(']') if self.state == State::Key => break,
+                Some(']') if self.state == State::Header => break,
+                Some('=') if self.state == State::Key => break,
+                Some(';') if self.state == State::Value => break,
+                Some('\n') | Some('\r') => break,
+                Some('%') if self.state == State::Exec => {
                     test_arg = true;
                     continue;
                 },
-                Some(n) => self.buf.push(n.clone()),
+                Some(&n) => self.buf.push(n),
                 None => return,
             }
 
@@ -305,55 +299,56 @@ impl Iterator for Tokens<'_> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::parser::Parser;
 
     #[test]
     fn test_read_text() {
         let p = Parser::new("abc]=\n");
 
         let mut t = p.tokens();
-        t.state = State::ReadHeader;
+        t.state = State::Header;
         t.read_text();
         assert_eq!(t.buf, "abc");
 
         let mut t = p.tokens();
-        t.state = State::ReadValue;
+        t.state = State::Value;
         t.read_text();
         assert_eq!(t.buf, "abc]=");
 
         let p = Parser::new("abc%f=\n");
         
         let mut t = p.tokens();
-        t.state = State::ReadKey;
+        t.state = State::Key;
         t.read_text();
         assert_eq!(t.buf, "abc%f");
 
         let mut t = p.tokens();
-        t.state = State::ReadExec;
+        t.state = State::Exec;
         t.read_text();
         assert_eq!(t.buf, "abc");
 
         let mut t = p.tokens();
-        t.state = State::ReadValue;
+        t.state = State::Value;
         t.read_text();
         assert_eq!(t.buf, "abc%f=");
 
         let mut t = p.tokens();
-        t.state = State::ReadHeader;
+        t.state = State::Header;
         t.read_text();
         assert_eq!(t.buf, "abc%f=");
 
-        let mut t = Tokens::new("value #comment");
-        t.state = State::ReadValue;
+        let mut t = Tokens::new("value #1");
+        t.state = State::Value;
         t.read_text();
-        assert_eq!(t.buf, "value ");
+        assert_eq!(t.buf, "value #1");
 
         let mut t = Tokens::new("text1;text2");
-        t.state = State::ReadValue;
+        t.state = State::Value;
         t.read_text();
         assert_eq!(t.buf, "text1");
 
         let mut t = Tokens::new("text1\n");
-        t.state = State::ReadValue;
+        t.state = State::Value;
         t.read_text();
         assert_eq!(t.buf, "text1");
     }
@@ -361,7 +356,7 @@ mod tests {
     #[test]
     fn test_advance_line() {
         let mut t = Tokens::new("\n\n\r\n\r");
-        t.state = State::ReadValue;
+        t.state = State::Value;
         
         assert_eq!(t.data.pos.row, 0);
         assert_eq!(t.data.pos.col, 0);
@@ -391,11 +386,8 @@ mod tests {
 
     #[test]
     fn test_skip_comment() {
-        let mut t = Tokens::new("text #comment!\r\nmore text");
-        t.state = State::ReadValue;
-
-        t.read_text();
-        assert_eq!(t.buf, "text ");
+        let mut t = Tokens::new("#comment!\r\nmore text");
+        t.state = State::Value;
 
         t.skip_comment();
         assert_eq!(t.data.pos.row, 1);
@@ -408,7 +400,7 @@ mod tests {
     #[test]
     fn test_skip_whitespace() {
         let mut t = Tokens::new("\t\t     text\r\n   \t\t");
-        t.state = State::ReadValue;
+        t.state = State::Value;
 
         t.skip_whitespace();
         assert_eq!(t.data.pos.row, 0);
@@ -430,16 +422,16 @@ mod tests {
     fn test_next_token() {
         let mut t = Tokens::new(r#"
         [header]
-        key1[en]=Hello World! [text] = stuff #this is a comment
+        key1[en]=Hello World! [text] = stuff #not a comment
         key2=./hello %F lol
         Exec=/usr/bin/app %f --arg %%
         #comment on a line
         key3=list;of;stuff!
         "#);
 
-        assert_eq!(t.state, State::ReadKey);
+        assert_eq!(t.state, State::Key);
         assert!(t.next_token().unwrap().is_left_bracket());
-        assert_eq!(t.state, State::ReadHeader);
+        assert_eq!(t.state, State::Header);
 
         let tok = t.next_token().unwrap();
         assert!(tok.is_text());
@@ -448,7 +440,7 @@ mod tests {
         }
 
         assert!(t.next_token().unwrap().is_right_bracket());
-        assert_eq!(t.state, State::ReadKey);
+        assert_eq!(t.state, State::Key);
         
         let tok = t.next_token().unwrap();
         assert!(tok.is_text());
@@ -456,7 +448,7 @@ mod tests {
             assert_eq!(val, "key1");
         }
 
-        assert_eq!(t.state, State::ReadKey);
+        assert_eq!(t.state, State::Key);
         assert!(t.next_token().unwrap().is_left_bracket());
         
         let tok = t.next_token().unwrap();
@@ -467,12 +459,12 @@ mod tests {
 
         assert!(t.next_token().unwrap().is_right_bracket());
         assert!(t.next_token().unwrap().is_equal());
-        assert_eq!(t.state, State::ReadValue);
+        assert_eq!(t.state, State::Value);
 
         let tok = t.next_token().unwrap();
         assert!(tok.is_text());
         if let TokenKind::Text(val) = tok.kind {
-            assert_eq!(val, "Hello World! [text] = stuff ");
+            assert_eq!(val, "Hello World! [text] = stuff #not a comment");
         }
 
         let tok = t.next_token().unwrap();
@@ -481,9 +473,9 @@ mod tests {
             assert_eq!(val, "key2")
         }
         
-        assert_eq!(t.state, State::ReadKey);
+        assert_eq!(t.state, State::Key);
         assert!(t.next_token().unwrap().is_equal());
-        assert_eq!(t.state, State::ReadValue);
+        assert_eq!(t.state, State::Value);
 
         let tok = t.next_token().unwrap();
         assert!(tok.is_text());
@@ -497,9 +489,9 @@ mod tests {
             assert_eq!(val, "Exec");
         }
 
-        assert_eq!(t.state, State::ReadKey);
+        assert_eq!(t.state, State::Key);
         assert!(t.next_token().unwrap().is_equal());
-        assert_eq!(t.state, State::ReadExec);
+        assert_eq!(t.state, State::Exec);
 
         let tok = t.next_token().unwrap();
         assert!(tok.is_text());
@@ -507,7 +499,7 @@ mod tests {
             assert_eq!(val, "/usr/bin/app ");
         }
 
-        assert_eq!(t.state, State::ReadExec);
+        assert_eq!(t.state, State::Exec);
         
         let tok = t.next_token().unwrap();
         assert!(tok.is_argument());
@@ -515,7 +507,7 @@ mod tests {
             assert_eq!(c, 'f');
         }
 
-        assert_eq!(t.state, State::ReadExec);
+        assert_eq!(t.state, State::Exec);
 
         let tok = t.next_token().unwrap();
         assert!(tok.is_text());
@@ -529,9 +521,9 @@ mod tests {
             assert_eq!(val, "key3");
         }
 
-        assert_eq!(t.state, State::ReadKey);
+        assert_eq!(t.state, State::Key);
         assert!(t.next_token().unwrap().is_equal());
-        assert_eq!(t.state, State::ReadValue);
+        assert_eq!(t.state, State::Value);
 
         let tok = t.next_token().unwrap();
         assert!(tok.is_text());
@@ -539,7 +531,7 @@ mod tests {
             assert_eq!(val, "list");
         }
 
-        assert_eq!(t.state, State::ReadValue);
+        assert_eq!(t.state, State::Value);
         assert!(t.next_token().unwrap().is_semicolon());
 
         let tok = t.next_token().unwrap();
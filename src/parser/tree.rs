@@ -1,24 +1,40 @@
-use std::fmt;
+use std::fmt::{self, Debug, Write};
+
+use super::span::Span;
+use super::tokens::Token;
 
 pub struct Node {
     pub span: Span,
     pub tokens: Vec<Token>,
 }
 
+impl Node {
+    pub fn new(span: Span) -> Node {
+        Node { span, tokens: Vec::new() }
+    }
+
+    pub fn push(&mut self, tok: Token) {
+        if self.tokens.is_empty() {
+            self.span.start = tok.span.start;
+        }
+
+        self.span.end = tok.span.end;
+        self.tokens.push(tok);
+    }
+}
+
 impl Debug for Node {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        first = true;
+        let mut first = true;
 
-        for tok in self.tokens {
+        for tok in &self.tokens {
             if !first {
-                f.write_char(' ');
+                f.write_char(' ')?;
             }
 
             first = false;
 
-            if let Err(_) = f.write_fmt("{:?}", tok) {
-                return Err(fmt::Error);
-            }
+            write!(f, "{:?}", tok)?;
         }
 
         Ok(())
@@ -32,26 +48,24 @@ pub struct Section {
 }
 
 impl Debug for Section {
-    fn fmt(&self, f: fmt::Formatter) -> Result<(), fmt::Error> {
-        f.write_fmt("SECTION({})", self.heading);
-
-        Ok(())
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "SECTION({})", self.heading)
     }
 }
 
 pub struct Entry {
     pub node: Node,
     pub key: String,
-    pub lang: String,
-    pub value: Vec<ValuePart>,
-
+    pub lang: Option<String>,
+    pub value: Value,
 }
 
 impl Debug for Entry {
-    fn fmt(&self, f: fmt::Formatter) -> Result<(), fmt::Error> {
-        f.write_fmt("ENTRY({} [{}])", self.key, self.lang);
-
-        Ok(())
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match &self.lang {
+            Some(lang) => write!(f, "ENTRY({} [{}])", self.key, lang),
+            None => write!(f, "ENTRY({})", self.key),
+        }
     }
 }
 
@@ -61,13 +75,11 @@ pub enum ValuePart {
 }
 
 impl Debug for ValuePart {
-    fn fmt(&self, f: fmt::Formatter) -> Result<(), fmt::Error> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
-            &Literal(s) => fmt.write_fmt("\"{}\"", s),
-            &Parameter(c) => fmt.write_fmt("%{}", c),
+            ValuePart::Literal(s) => write!(f, "\"{}\"", s),
+            ValuePart::Parameter(c) => write!(f, "%{}", c),
         }
-
-        Ok(())
     }
 }
 
@@ -77,13 +89,11 @@ pub enum ValueKind {
 }
 
 impl Debug for ValueKind {
-    fn fmt(&self, f: fmt::Formatter) -> Result<(), fmt::Error> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
-            &Simple(s) => fmt.write_fmt("\"{}\"", s),
-            &Exec(ss) => fmt.write_fmt("EXEC({})", ss.join(" ")),
+            ValueKind::Simple(s) => write!(f, "\"{}\"", s),
+            ValueKind::Exec(parts) => write!(f, "EXEC({:?})", parts),
         }
-        
-        Ok(())
     }
 }
 
@@ -92,8 +102,23 @@ pub struct Value {
     pub kind: ValueKind,
 }
 
+impl Value {
+    /// Reassemble the value as it was written in the file, escapes and field codes included
+    pub fn raw(&self) -> String {
+        match &self.kind {
+            ValueKind::Simple(s) => s.clone(),
+            ValueKind::Exec(parts) => parts.iter()
+                .map(|part| match part {
+                    ValuePart::Literal(s) => s.clone(),
+                    ValuePart::Parameter(c) => format!("%{}", c),
+                })
+                .collect(),
+        }
+    }
+}
+
 impl Debug for Value {
-    fn fmt(&self, f: fmt::Formatter) -> Result<(), fmt::Error> {
-        f.write_fmt("VALUE {:?}", self.kind);
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "VALUE {:?}", self.kind)
     }
-}
\ No newline at end of file
+}
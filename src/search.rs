@@ -0,0 +1,159 @@
+//! Ranking entries against a search query
+//!
+//! Each word of the query is matched on its own against the fields people search by, ignoring
+//! case and accents. A word can match a field exactly, as a prefix, at the start of a word, as a
+//! substring, or as a fuzzy subsequence (`ffx` in `Firefox`), from best to worst. The quality of
+//! the match is multiplied by the weight of the field, so a match in the name beats the same match
+//! in the comment, and an entry's score is the sum of its best score for every word. Entries that
+//! miss any word of the query don't match at all.
+
+use std::path::Path;
+
+use crate::collate::fold;
+use crate::entry::DesktopEntry;
+use crate::exec::Exec;
+
+/// Weights of the fields that are searched
+const NAME_WEIGHT: u32 = 10;
+const GENERIC_NAME_WEIGHT: u32 = 7;
+const KEYWORDS_WEIGHT: u32 = 6;
+const EXEC_WEIGHT: u32 = 5;
+const COMMENT_WEIGHT: u32 = 3;
+
+#[derive(Debug, Clone)]
+pub struct Query {
+    terms: Vec<String>,
+}
+
+impl Query {
+    pub fn new(query: &str) -> Query {
+        Query { terms: query.split_whitespace().map(fold).collect() }
+    }
+
+    /// Score an entry against the query using its values for `lang`, or `None` if some word of
+    /// the query doesn't match it. Higher is better.
+    pub fn score(&self, entry: &DesktopEntry, lang: Option<&str>) -> Option<u32> {
+        let mut fields = Vec::new();
+        let mut push = |value: Option<String>, weight: u32| {
+            if let Some(value) = value {
+                fields.push((fold(&value), weight));
+            }
+        };
+
+        push(entry.get_localized("Name", lang), NAME_WEIGHT);
+        push(entry.get_localized("GenericName", lang), GENERIC_NAME_WEIGHT);
+        for keyword in entry.get_localized_list("Keywords", lang).unwrap_or_default() {
+            push(Some(keyword), KEYWORDS_WEIGHT);
+        }
+
+        push(program_name(entry), EXEC_WEIGHT);
+        push(entry.get_localized("Comment", lang), COMMENT_WEIGHT);
+
+        self.terms.iter()
+            .map(|term| fields.iter()
+                .filter_map(|(value, weight)| match_quality(term, value).map(|quality| quality * weight))
+                .max())
+            .sum()
+    }
+}
+
+/// The file name of the program an entry runs
+fn program_name(entry: &DesktopEntry) -> Option<String> {
+    let program = Exec::from_entry(entry).ok()?.program()?;
+    Path::new(&program).file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+}
+
+/// How well a folded search term matches a folded value, from 0 to 100
+pub fn match_quality(term: &str, value: &str) -> Option<u32> {
+    if term.is_empty() {
+        return Some(0);
+    }
+
+    if value == term {
+        return Some(100);
+    }
+
+    if value.starts_with(term) {
+        return Some(90);
+    }
+
+    if let Some(idx) = value.find(term) {
+        let at_word_start = value[..idx].chars().last().is_none_or(|c| !c.is_alphanumeric());
+        return Some(if at_word_start { 80 } else { 60 });
+    }
+
+    subsequence_quality(term, value)
+}
+
+/// Score a term whose characters all appear in order in the value. Characters that follow the
+/// previous match or start a word count extra, so `gte` matches `gnome text editor` better than
+/// `getter`.
+fn subsequence_quality(term: &str, value: &str) -> Option<u32> {
+    let mut points = 0;
+    let mut prev: Option<char> = None;
+    let mut matched_prev = false;
+    let mut term_chars = term.chars().peekable();
+
+    for c in value.chars() {
+        match term_chars.peek() {
+            Some(t) if *t == c => {
+                points += 1;
+                if matched_prev {
+                    points += 1;
+                }
+
+                if prev.is_none_or(|p| !p.is_alphanumeric()) {
+                    points += 1;
+                }
+
+                term_chars.next();
+                matched_prev = true;
+            },
+            Some(..) => matched_prev = false,
+            None => break,
+        }
+
+        prev = Some(c);
+    }
+
+    if term_chars.peek().is_some() {
+        return None;
+    }
+
+    let max = 3 * term.chars().count() as u32;
+    Some(10 + 40 * points / max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(contents: &str) -> DesktopEntry {
+        DesktopEntry::parse("test.desktop", format!("[Desktop Entry]\n{}", contents)).unwrap()
+    }
+
+    #[test]
+    fn test_match_quality() {
+        assert_eq!(match_quality("firefox", "firefox"), Some(100));
+        assert_eq!(match_quality("fire", "firefox"), Some(90));
+        assert_eq!(match_quality("browser", "web browser"), Some(80));
+        assert_eq!(match_quality("fox", "firefox"), Some(60));
+        assert!(match_quality("ffx", "firefox").unwrap() < 60);
+        assert!(match_quality("gte", "gnome text editor") > match_quality("gte", "getter"));
+        assert_eq!(match_quality("xyz", "firefox"), None);
+    }
+
+    #[test]
+    fn test_score() {
+        let firefox = entry("Name=Firefox\nGenericName=Web Browser\nKeywords=Internet;WWW;\nExec=/usr/lib/firefox/firefox %u\n");
+        let editor = entry("Name=Text Editor\nComment=Edit text files in a browser-like view\nExec=gedit %U\n");
+
+        let query = Query::new("browser");
+        assert!(query.score(&firefox, None) > query.score(&editor, None));
+        assert!(Query::new("firefox www").score(&firefox, None).is_some());
+        assert_eq!(Query::new("firefox gedit").score(&firefox, None), None);
+        assert_eq!(Query::new("GEDIT").score(&editor, None), Some(EXEC_WEIGHT * 100));
+        assert_eq!(Query::new("Éditor").score(&editor, None), Some(NAME_WEIGHT * 80));
+    }
+}
@@ -0,0 +1,431 @@
+//! User-defined output templates
+//!
+//! A template is literal text with placeholders in braces:
+//!
+//! ```text
+//! {name} ({generic_name|default:'none'})\t{path}
+//! ```
+//!
+//! A placeholder names a value source, optionally followed by a locale in brackets
+//! (`{name[fr]}`) and any number of filters separated by `|`. Filters take arguments after `:`,
+//! either bare or quoted with `'` or `"`:
+//!
+//! * `lower`, `upper` change case
+//! * `truncate:N[:SUFFIX]` cuts the value to `N` characters, appending `SUFFIX` if it was cut
+//! * `join:SEP` joins a list with `SEP` instead of `;`
+//! * `shell` quotes the value for a POSIX shell (each item, for a list)
+//! * `default:VALUE` replaces a missing or empty value
+//! * `prefix:TEXT`, `suffix:TEXT` add text around a value only when it isn't empty
+//!
+//! `{?source}...{/}` only renders its contents when the source has a value, and
+//! `{!source}...{/}` only when it doesn't. Literal braces are written `{{` and `}}`, and `\t`,
+//! `\n` and `\\` are recognized so templates can be passed in single quotes from a shell.
+//!
+//! Sources are only named here; resolving them to values is left to the caller, which sees each
+//! distinct source through `Template::sources`.
+
+use thiserror::Error;
+
+use crate::output::{shell_quote, Field};
+
+#[derive(Error, Debug, PartialEq)]
+#[error("template error at column {0}: {1}")]
+pub struct TemplateError(pub usize, pub String);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    /// Position of this source in `Template::sources`
+    pub idx: usize,
+    pub name: String,
+    pub locale: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Lower,
+    Upper,
+    Truncate(usize, String),
+    Join(String),
+    Shell,
+    Default(String),
+    Prefix(String),
+    Suffix(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    Value(usize, Vec<Filter>),
+    Cond { source: usize, negate: bool, body: Vec<Node> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    nodes: Vec<Node>,
+    sources: Vec<Source>,
+}
+
+impl Template {
+    pub fn parse(input: &str) -> Result<Template, TemplateError> {
+        let mut parser = TemplateParser {
+            chars: input.chars().collect(),
+            pos: 0,
+            sources: Vec::new(),
+        };
+
+        let nodes = parser.parse_nodes(false)?;
+        Ok(Template { nodes, sources: parser.sources })
+    }
+
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
+    }
+
+    /// Render the template, resolving every source through `resolve`
+    pub fn render<F: Fn(&Source) -> Field>(&self, resolve: F) -> String {
+        let fields = self.sources.iter()
+            .map(&resolve)
+            .collect::<Vec<Field>>();
+
+        let mut out = String::new();
+        render_nodes(&self.nodes, &fields, &mut out);
+        out
+    }
+}
+
+fn render_nodes(nodes: &[Node], fields: &[Field], out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Value(source, filters) => {
+                let field = filters.iter()
+                    .fold(fields[*source].clone(), apply_filter);
+
+                out.push_str(&field.to_text());
+            },
+            Node::Cond { source, negate, body } => {
+                if fields[*source].is_present() != *negate {
+                    render_nodes(body, fields, out);
+                }
+            },
+        }
+    }
+}
+
+fn apply_filter(field: Field, filter: &Filter) -> Field {
+    match filter {
+        Filter::Lower => map_text(field, |s| s.to_lowercase()),
+        Filter::Upper => map_text(field, |s| s.to_uppercase()),
+        Filter::Truncate(len, suffix) => {
+            let text = field.to_text();
+            if text.chars().count() > *len {
+                Field::Text(text.chars().take(*len).collect::<String>() + suffix)
+            } else {
+                Field::Text(text)
+            }
+        },
+        Filter::Join(sep) => match field {
+            Field::List(items) => Field::Text(items.join(sep)),
+            field => field,
+        },
+        Filter::Shell => match field {
+            Field::List(items) => Field::Text(items.iter()
+                .map(|item| shell_quote(item).into_owned())
+                .collect::<Vec<String>>()
+                .join(" ")),
+            field => Field::Text(shell_quote(&field.to_text()).into_owned()),
+        },
+        Filter::Default(value) if !field.is_present() => Field::Text(value.clone()),
+        Filter::Prefix(prefix) if field.is_present() => Field::Text(format!("{}{}", prefix, field.to_text())),
+        Filter::Suffix(suffix) if field.is_present() => Field::Text(format!("{}{}", field.to_text(), suffix)),
+        Filter::Default(..) | Filter::Prefix(..) | Filter::Suffix(..) => field,
+    }
+}
+
+fn map_text<F: Fn(&str) -> String>(field: Field, f: F) -> Field {
+    match field {
+        Field::Text(s) => Field::Text(f(&s)),
+        Field::List(items) => Field::List(items.iter().map(|s| f(s)).collect()),
+        Field::Localized(values) => Field::Localized(values.into_iter()
+            .map(|(locale, s)| (locale, f(&s)))
+            .collect()),
+        field => field,
+    }
+}
+
+struct TemplateParser {
+    chars: Vec<char>,
+    pos: usize,
+    sources: Vec<Source>,
+}
+
+impl TemplateParser {
+    fn parse_nodes(&mut self, nested: bool) -> Result<Vec<Node>, TemplateError> {
+        let mut nodes = Vec::new();
+        let mut text = String::new();
+
+        while let Some(c) = self.next() {
+            match c {
+                '{' if self.peek() == Some('{') => {
+                    self.pos += 1;
+                    text.push('{');
+                },
+                '}' if self.peek() == Some('}') => {
+                    self.pos += 1;
+                    text.push('}');
+                },
+                '}' => return Err(self.error(self.pos - 1, "unmatched `}`; write `}}` for a literal brace")),
+                '\\' => match self.peek() {
+                    Some('t') => { self.pos += 1; text.push('\t'); },
+                    Some('n') => { self.pos += 1; text.push('\n'); },
+                    Some('\\') => { self.pos += 1; text.push('\\'); },
+                    _ => text.push('\\'),
+                },
+                '{' => {
+                    let start = self.pos - 1;
+                    if !text.is_empty() {
+                        nodes.push(Node::Text(std::mem::take(&mut text)));
+                    }
+
+                    match self.peek() {
+                        Some('/') => {
+                            self.pos += 1;
+                            self.expect_close(start)?;
+
+                            if !nested {
+                                return Err(self.error(start, "`{/}` without an open `{?...}` or `{!...}`"));
+                            }
+
+                            return Ok(nodes);
+                        },
+                        Some(c @ '?') | Some(c @ '!') => {
+                            self.pos += 1;
+                            let source = self.parse_source()?;
+                            self.expect_close(start)?;
+
+                            let body = self.parse_nodes(true)?;
+                            nodes.push(Node::Cond { source, negate: c == '!', body });
+                        },
+                        _ => {
+                            let source = self.parse_source()?;
+                            let filters = self.parse_filters()?;
+                            self.expect_close(start)?;
+
+                            nodes.push(Node::Value(source, filters));
+                        },
+                    }
+                },
+                c => text.push(c),
+            }
+        }
+
+        if nested {
+            return Err(self.error(self.chars.len(), "missing `{/}` to close a conditional"));
+        }
+
+        if !text.is_empty() {
+            nodes.push(Node::Text(text));
+        }
+
+        Ok(nodes)
+    }
+
+    fn parse_source(&mut self) -> Result<usize, TemplateError> {
+        self.skip_spaces();
+
+        let start = self.pos;
+        let name = self.take_while(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == '/' || c == ':' || c == '.' || c == '@');
+        if name.is_empty() {
+            return Err(self.error(start, "expected a column or key name"));
+        }
+
+        let locale = if self.peek() == Some('[') {
+            self.pos += 1;
+            let locale = self.take_while(|c| c != ']' && c != '}');
+            if self.next() != Some(']') {
+                return Err(self.error(start, "unterminated locale; expected `]`"));
+            }
+
+            Some(locale)
+        } else {
+            None
+        };
+
+        self.skip_spaces();
+
+        let existing = self.sources.iter()
+            .find(|source| source.name == name && source.locale == locale)
+            .map(|source| source.idx);
+
+        Ok(existing.unwrap_or_else(|| {
+            let idx = self.sources.len();
+            self.sources.push(Source { idx, name, locale });
+            idx
+        }))
+    }
+
+    fn parse_filters(&mut self) -> Result<Vec<Filter>, TemplateError> {
+        let mut filters = Vec::new();
+
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            self.skip_spaces();
+
+            let start = self.pos;
+            let name = self.take_while(|c| c.is_alphanumeric() || c == '-' || c == '_');
+
+            let mut args = Vec::new();
+            while self.peek() == Some(':') {
+                self.pos += 1;
+                args.push(self.parse_arg()?);
+            }
+
+            self.skip_spaces();
+
+            let arg = |idx: usize| args.get(idx).cloned();
+            let filter = match (name.as_str(), args.len()) {
+                ("lower", 0) | ("lowercase", 0) => Filter::Lower,
+                ("upper", 0) | ("uppercase", 0) => Filter::Upper,
+                ("truncate", 1) | ("truncate", 2) => {
+                    let len = args[0].parse()
+                        .map_err(|_| self.error(start, "`truncate` needs a number of characters"))?;
+
+                    Filter::Truncate(len, arg(1).unwrap_or_default())
+                },
+                ("join", 0) => Filter::Join(" ".into()),
+                ("join", 1) => Filter::Join(args[0].clone()),
+                ("shell", 0) | ("shell-escape", 0) => Filter::Shell,
+                ("default", 1) => Filter::Default(args[0].clone()),
+                ("prefix", 1) => Filter::Prefix(args[0].clone()),
+                ("suffix", 1) => Filter::Suffix(args[0].clone()),
+                ("lower", _) | ("lowercase", _) | ("upper", _) | ("uppercase", _) | ("truncate", _)
+                | ("join", _) | ("shell", _) | ("shell-escape", _) | ("default", _) | ("prefix", _)
+                | ("suffix", _) => {
+                    return Err(self.error(start, &format!("wrong number of arguments for `{}`", name)));
+                },
+                _ => return Err(self.error(start, &format!("unknown filter `{}`", name))),
+            };
+
+            filters.push(filter);
+        }
+
+        Ok(filters)
+    }
+
+    fn parse_arg(&mut self) -> Result<String, TemplateError> {
+        let start = self.pos;
+
+        match self.peek() {
+            Some(quote @ '\'') | Some(quote @ '"') => {
+                self.pos += 1;
+
+                let mut arg = String::new();
+                loop {
+                    match self.next() {
+                        Some('\\') => match self.next() {
+                            Some('t') => arg.push('\t'),
+                            Some('n') => arg.push('\n'),
+                            Some(c) => arg.push(c),
+                            None => break,
+                        },
+                        Some(c) if c == quote => return Ok(arg),
+                        Some(c) => arg.push(c),
+                        None => break,
+                    }
+                }
+
+                Err(self.error(start, "unterminated quoted argument"))
+            },
+            _ => Ok(self.take_while(|c| c != ':' && c != '|' && c != '}')),
+        }
+    }
+
+    fn expect_close(&mut self, start: usize) -> Result<(), TemplateError> {
+        self.skip_spaces();
+
+        match self.next() {
+            Some('}') => Ok(()),
+            Some(c) => Err(self.error(self.pos - 1, &format!("unexpected `{}` in placeholder", c))),
+            None => Err(self.error(start, "unterminated placeholder; expected `}`")),
+        }
+    }
+
+    fn take_while<F: Fn(char) -> bool>(&mut self, pred: F) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(&pred) {
+            self.pos += 1;
+        }
+
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn skip_spaces(&mut self) {
+        self.take_while(|c| c == ' ');
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        if c.is_some() {
+            self.pos += 1;
+        }
+
+        c
+    }
+
+    fn error(&self, pos: usize, message: &str) -> TemplateError {
+        TemplateError(pos + 1, message.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, values: &[(&str, Field)]) -> String {
+        let template = Template::parse(template).unwrap();
+        template.render(|source| values.iter()
+            .find(|(name, _)| *name == source.name)
+            .map_or(Field::Empty, |(_, field)| field.clone()))
+    }
+
+    #[test]
+    fn test_render() {
+        let values = [
+            ("name", Field::Text("Firefox".into())),
+            ("generic_name", Field::Text("Web Browser".into())),
+            ("categories", Field::List(vec!["Network".into(), "WebBrowser".into()])),
+            ("path", Field::Text("/usr/share/applications/firefox.desktop".into())),
+        ];
+
+        assert_eq!(render(r"{name} ({generic_name|default:''})\t{path}", &values),
+            "Firefox (Web Browser)\t/usr/share/applications/firefox.desktop");
+        assert_eq!(render("{name|upper|truncate:4:'…'} {categories|join:', '}", &values), "FIRE… Network, WebBrowser");
+        assert_eq!(render("{comment|default:'n/a'}{comment|prefix:' - '}", &values), "n/a");
+        assert_eq!(render("{?generic_name}{generic_name}{/}{!comment} (no comment){/}", &values), "Web Browser (no comment)");
+        assert_eq!(render("{{{name|lower|shell}}}", &values), "{firefox}");
+        assert_eq!(render("{generic_name|shell} {missing|shell}", &values), "'Web Browser' ''");
+    }
+
+    #[test]
+    fn test_sources() {
+        let template = Template::parse("{name}{name[fr]}{?name}{name}{/}").unwrap();
+
+        assert_eq!(template.sources().len(), 2);
+        assert_eq!(template.sources()[1].locale.as_deref(), Some("fr"));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(Template::parse("ab {name").unwrap_err().0, 4);
+        assert_eq!(Template::parse("{name|bogus}").unwrap_err().0, 7);
+        assert_eq!(Template::parse("{name|truncate:x}").unwrap_err().0, 7);
+        assert_eq!(Template::parse("{?name} dangling").unwrap_err().0, 17);
+        assert_eq!(Template::parse("text {/}").unwrap_err().0, 6);
+        assert_eq!(Template::parse("a } b").unwrap_err().0, 3);
+        assert_eq!(Template::parse("{}").unwrap_err().0, 2);
+    }
+}
@@ -0,0 +1,98 @@
+//! Choosing a terminal emulator for entries with `Terminal=true`
+//!
+//! A terminal is given as a command, like `alacritty` or `wezterm start --`. A single word names
+//! the terminal, and if it's one we know, the arguments it needs to run a command are added for
+//! it; otherwise `-e`, which most terminals accept, is assumed. A command with several words is
+//! used as it is, with the command to run appended.
+
+use std::env;
+use std::path::Path;
+
+use crate::tryexec::find_executable;
+
+/// Terminals tried in order when none is configured, with the arguments that come before the
+/// command to run
+const KNOWN_TERMINALS: &[(&str, &[&str])] = &[
+    ("xdg-terminal-exec", &[]),
+    ("kgx", &["--"]),
+    ("gnome-terminal", &["--"]),
+    ("konsole", &["-e"]),
+    ("xfce4-terminal", &["-x"]),
+    ("alacritty", &["-e"]),
+    ("kitty", &[]),
+    ("foot", &[]),
+    ("wezterm", &["start", "--"]),
+    ("terminator", &["-x"]),
+    ("urxvt", &["-e"]),
+    ("st", &["-e"]),
+    ("xterm", &["-e"]),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Terminal {
+    command: Vec<String>,
+}
+
+impl Terminal {
+    pub fn from_command(command: &str) -> Option<Terminal> {
+        let words = command.split_whitespace()
+            .map(String::from)
+            .collect::<Vec<String>>();
+
+        match words.as_slice() {
+            [] => None,
+            [program] => {
+                let name = Path::new(program).file_name()
+                    .map_or(program.clone(), |name| name.to_string_lossy().into_owned());
+                let args = KNOWN_TERMINALS.iter()
+                    .find(|(known, _)| *known == name)
+                    .map_or(&["-e"][..], |(_, args)| *args);
+
+                let mut command = words.clone();
+                command.extend(args.iter().map(|arg| arg.to_string()));
+                Some(Terminal { command })
+            },
+            _ => Some(Terminal { command: words }),
+        }
+    }
+
+    /// Find the terminal to use: the configured one, then `$TERMINAL`, then the first known
+    /// terminal installed, which puts `xdg-terminal-exec` first
+    pub fn detect(configured: Option<&str>) -> Option<Terminal> {
+        if let Some(terminal) = configured.and_then(Terminal::from_command) {
+            return Some(terminal);
+        }
+
+        if let Some(terminal) = env::var("TERMINAL").ok().as_deref().and_then(Terminal::from_command) {
+            return Some(terminal);
+        }
+
+        KNOWN_TERMINALS.iter()
+            .find(|(program, _)| find_executable(program).is_some())
+            .and_then(|(program, _)| Terminal::from_command(program))
+    }
+
+    /// The command line that runs `argv` in the terminal
+    pub fn wrap(&self, argv: Vec<String>) -> Vec<String> {
+        self.command.iter().cloned().chain(argv).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wrap(terminal: &str) -> Vec<String> {
+        Terminal::from_command(terminal).unwrap().wrap(vec!["htop".into(), "-d".into(), "5".into()])
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("xfce4-terminal"), vec!["xfce4-terminal", "-x", "htop", "-d", "5"]);
+        assert_eq!(wrap("/usr/bin/wezterm"), vec!["/usr/bin/wezterm", "start", "--", "htop", "-d", "5"]);
+        assert_eq!(wrap("kitty"), vec!["kitty", "htop", "-d", "5"]);
+        assert_eq!(wrap("my-term"), vec!["my-term", "-e", "htop", "-d", "5"]);
+        assert_eq!(wrap("foot --app-id=tui"), vec!["foot", "--app-id=tui", "htop", "-d", "5"]);
+        assert_eq!(Terminal::from_command("  "), None);
+    }
+}
@@ -0,0 +1,116 @@
+//! Resolution of `TryExec` programs against the filesystem and `$PATH`
+
+use std::env;
+use std::fs::metadata;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TryExec {
+    /// The entry has no `TryExec` key
+    Unset,
+    /// The program was found and can be executed
+    Found(PathBuf),
+    /// No file with that name exists
+    Missing,
+    /// A file with that name exists, but it isn't executable
+    NotExecutable(PathBuf),
+}
+
+impl TryExec {
+    /// Resolve a `TryExec` value, which is either an absolute path or a name to look up in
+    /// `$PATH`
+    pub fn resolve(program: &str) -> TryExec {
+        TryExec::resolve_in(program, &search_path())
+    }
+
+    /// Resolve a `TryExec` value, looking names up in the given directories
+    fn resolve_in(program: &str, dirs: &[PathBuf]) -> TryExec {
+        let program = Path::new(program);
+
+        if program.is_absolute() {
+            return TryExec::check(program.to_path_buf());
+        }
+
+        let mut res = TryExec::Missing;
+        for dir in dirs {
+            match TryExec::check(dir.join(program)) {
+                found @ TryExec::Found(..) => return found,
+                not_exec @ TryExec::NotExecutable(..) if res == TryExec::Missing => res = not_exec,
+                _ => continue,
+            }
+        }
+
+        res
+    }
+
+    fn check(path: PathBuf) -> TryExec {
+        match metadata(&path) {
+            Ok(meta) if meta.is_file() && meta.permissions().mode() & 0o111 != 0 => TryExec::Found(path),
+            Ok(..) => TryExec::NotExecutable(path),
+            Err(..) => TryExec::Missing,
+        }
+    }
+
+    /// Whether the entry should be shown: either there was nothing to check or the program
+    /// was found
+    pub fn is_available(&self) -> bool {
+        match self {
+            TryExec::Unset | TryExec::Found(..) => true,
+            TryExec::Missing | TryExec::NotExecutable(..) => false,
+        }
+    }
+
+    pub fn status(&self) -> &'static str {
+        match self {
+            TryExec::Unset => "unset",
+            TryExec::Found(..) => "found",
+            TryExec::Missing => "missing",
+            TryExec::NotExecutable(..) => "not-executable",
+        }
+    }
+}
+
+/// Find an executable by name in `$PATH`, or check it directly if it's a path
+pub fn find_executable(program: &str) -> Option<PathBuf> {
+    match TryExec::resolve(program) {
+        TryExec::Found(path) => Some(path),
+        _ => None,
+    }
+}
+
+fn search_path() -> Vec<PathBuf> {
+    env::var_os("PATH")
+        .map_or(vec![], |path| env::split_paths(&path)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_resolve() {
+        let dir = env::temp_dir().join(format!("lsapp-tryexec-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let program = dir.join("program");
+        fs::write(&program, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let data = dir.join("data");
+        fs::write(&data, "").unwrap();
+        fs::set_permissions(&data, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let dirs = vec![PathBuf::from("/nonexistent"), dir.clone()];
+        assert_eq!(TryExec::resolve_in(program.to_str().unwrap(), &[]), TryExec::Found(program.clone()));
+        assert_eq!(TryExec::resolve_in(data.to_str().unwrap(), &dirs), TryExec::NotExecutable(data.clone()));
+        assert_eq!(TryExec::resolve_in("program", &dirs), TryExec::Found(program.clone()));
+        assert_eq!(TryExec::resolve_in("missing", &dirs), TryExec::Missing);
+        assert!(!TryExec::resolve_in("missing", &dirs).is_available());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
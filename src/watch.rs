@@ -0,0 +1,216 @@
+//! Watching source directories for changes to .desktop files
+//!
+//! Every source directory and all of its subdirectories get an inotify watch. Directories that
+//! are created or moved in later are picked up as their events arrive, so a package install that
+//! drops a new subdirectory of entries is seen like any other change. A source directory that
+//! doesn't exist yet is waited for by watching its closest existing parent.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::Duration;
+
+use color_eyre::Result;
+use eyre::WrapErr;
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use shellexpand::tilde;
+
+pub struct Watcher {
+    inotify: Inotify,
+    sources: Vec<PathBuf>,
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+    /// Source directories that don't exist, each waited for in `parents`
+    pending: Vec<PathBuf>,
+    /// Closest existing parents of the pending sources
+    parents: HashMap<WatchDescriptor, PathBuf>,
+    buffer: Vec<u8>,
+}
+
+impl Watcher {
+    pub fn new<S>(sources: S) -> Result<Watcher>
+    where
+        S: IntoIterator,
+        S::Item: AsRef<Path>
+    {
+        let inotify = Inotify::init()
+            .wrap_err("failed to initialize inotify")?;
+
+        let sources = sources.into_iter()
+            .filter_map(|source| source.as_ref().to_str().map(|dir| PathBuf::from(tilde(dir).as_ref())))
+            .collect::<Vec<PathBuf>>();
+
+        let mut watcher = Watcher {
+            inotify,
+            pending: sources.clone(),
+            sources,
+            dirs: HashMap::new(),
+            parents: HashMap::new(),
+            buffer: vec![0; 4096],
+        };
+
+        watcher.watch_pending();
+        Ok(watcher)
+    }
+
+    /// Block until something under the watched directories changes, then keep collecting
+    /// changes until none to .desktop files or directories have arrived for `debounce`.
+    /// Installing a package touches many files at once; this turns the burst into a single
+    /// wake-up.
+    pub fn wait(&mut self, debounce: Duration) -> Result<()> {
+        while !self.read(true)? {}
+
+        loop {
+            sleep(debounce);
+
+            if !self.read(false)? {
+                return Ok(());
+            }
+        }
+    }
+
+    fn watch_tree(&mut self, dir: &Path) {
+        // The directory might already be gone again; its parent's events cover that
+        if let Ok(wd) = self.inotify.add_watch(dir, watch_mask()) {
+            self.dirs.insert(wd, dir.to_path_buf());
+        }
+
+        for subdir in crate::list_dir(dir).1 {
+            self.watch_tree(&subdir);
+        }
+    }
+
+    /// Watch the pending sources that exist by now, and the closest existing parent of the
+    /// others. Returns whether any source appeared.
+    fn watch_pending(&mut self) -> bool {
+        let mut appeared = false;
+        for source in std::mem::take(&mut self.pending) {
+            if source.is_dir() {
+                self.watch_tree(&source);
+                appeared = true;
+                continue;
+            }
+
+            // The same mask as the tree, since a parent can be part of another source's tree
+            if let Some(parent) = source.ancestors().skip(1).find(|dir| dir.is_dir()) {
+                if let Ok(wd) = self.inotify.add_watch(parent, watch_mask()) {
+                    self.parents.insert(wd, parent.to_path_buf());
+                }
+            }
+
+            self.pending.push(source);
+        }
+
+        appeared
+    }
+
+    /// Read pending events, returning whether any of them concerned a .desktop file or a
+    /// directory of a source
+    fn read(&mut self, block: bool) -> Result<bool> {
+        let events = if block {
+            self.inotify.read_events_blocking(&mut self.buffer)
+        } else {
+            self.inotify.read_events(&mut self.buffer)
+        }.wrap_err("failed to read inotify events")?;
+
+        let mut relevant = false;
+        let mut new_dirs = Vec::new();
+        let mut check_pending = false;
+
+        for event in events {
+            if event.mask.contains(EventMask::Q_OVERFLOW) {
+                relevant = true;
+                continue;
+            }
+
+            if event.mask.contains(EventMask::IGNORED) {
+                self.parents.remove(&event.wd);
+
+                // A removed source is waited for again
+                if let Some(dir) = self.dirs.remove(&event.wd) {
+                    if self.sources.contains(&dir) {
+                        self.pending.push(dir);
+                        check_pending = true;
+                    }
+                }
+
+                continue;
+            }
+
+            let is_dir = event.mask.contains(EventMask::ISDIR);
+            let created = event.mask.intersects(EventMask::CREATE | EventMask::MOVED_TO);
+            if is_dir && created && self.parents.contains_key(&event.wd) {
+                check_pending = true;
+            }
+
+            let path = match (self.dirs.get(&event.wd), event.name) {
+                (Some(dir), Some(name)) => dir.join(name),
+                _ => continue,
+            };
+
+            if !is_relevant(&path, is_dir) {
+                continue;
+            }
+
+            relevant = true;
+            if is_dir && created {
+                new_dirs.push(path);
+            }
+        }
+
+        for dir in new_dirs {
+            self.watch_tree(&dir);
+        }
+
+        if check_pending && self.watch_pending() {
+            relevant = true;
+        }
+
+        Ok(relevant)
+    }
+}
+
+fn watch_mask() -> WatchMask {
+    WatchMask::CREATE
+        | WatchMask::DELETE
+        | WatchMask::MODIFY
+        | WatchMask::CLOSE_WRITE
+        | WatchMask::MOVE
+        | WatchMask::ONLYDIR
+}
+
+/// Whether a change to a path in a source can change the listing. Other files, like the
+/// `mimeinfo.cache` next to the entries, are ignored and don't hold back the debounce.
+fn is_relevant(path: &Path, is_dir: bool) -> bool {
+    is_dir || path.extension().is_some_and(|ext| ext == "desktop")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_missing_source() {
+        let root = env::temp_dir().join(format!("lsapp-watch-test-{}", std::process::id()));
+        let source = root.join("share/applications");
+        fs::create_dir_all(&root).unwrap();
+
+        let mut watcher = Watcher::new(&[&source]).unwrap();
+        assert!(watcher.dirs.is_empty());
+
+        fs::create_dir(root.join("share")).unwrap();
+        assert!(!watcher.read(false).unwrap());
+
+        fs::create_dir(&source).unwrap();
+        assert!(watcher.read(false).unwrap());
+
+        fs::write(source.join("mimeinfo.cache"), "").unwrap();
+        assert!(!watcher.read(false).unwrap());
+
+        fs::write(source.join("vim.desktop"), "").unwrap();
+        assert!(watcher.read(false).unwrap());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}